@@ -0,0 +1,47 @@
+// Per-stream interrupt flags, as found at offset 0 of LISR/HISR/LIFCR/HIFCR.
+// Use `stream_flag_shift` to move them to the position of a given stream.
+pub const ISR_FEIF: u32 = 0x00000001;
+pub const ISR_DMEIF: u32 = 0x00000004;
+pub const ISR_TEIF: u32 = 0x00000008;
+pub const ISR_HTIF: u32 = 0x00000010;
+pub const ISR_TCIF: u32 = 0x00000020;
+pub const ISR_MASK: u32 = 0x0000003D;
+
+pub const SCR_EN: u32 = 0x00000001;
+pub const SCR_DMEIE: u32 = 0x00000002;
+pub const SCR_TEIE: u32 = 0x00000004;
+pub const SCR_HTIE: u32 = 0x00000008;
+pub const SCR_TCIE: u32 = 0x00000010;
+pub const SCR_PFCTRL: u32 = 0x00000020;
+pub const SCR_DIR_MASK: u32 = 0x000000C0;
+pub const SCR_DIR_SHIFT: u32 = 6;
+pub const SCR_CIRC: u32 = 0x00000100;
+pub const SCR_PINC: u32 = 0x00000200;
+pub const SCR_MINC: u32 = 0x00000400;
+pub const SCR_PSIZE_MASK: u32 = 0x00001800;
+pub const SCR_PSIZE_SHIFT: u32 = 11;
+pub const SCR_MSIZE_MASK: u32 = 0x00006000;
+pub const SCR_MSIZE_SHIFT: u32 = 13;
+pub const SCR_PINCOS: u32 = 0x00008000;
+pub const SCR_PL_MASK: u32 = 0x00030000;
+pub const SCR_PL_SHIFT: u32 = 16;
+pub const SCR_DBM: u32 = 0x00040000;
+pub const SCR_CT: u32 = 0x00080000;
+pub const SCR_PBURST_MASK: u32 = 0x00600000;
+pub const SCR_PBURST_SHIFT: u32 = 21;
+pub const SCR_MBURST_MASK: u32 = 0x01800000;
+pub const SCR_MBURST_SHIFT: u32 = 23;
+pub const SCR_CHSEL_MASK: u32 = 0x0E000000;
+pub const SCR_CHSEL_SHIFT: u32 = 25;
+
+pub const SFCR_FTH_MASK: u32 = 0x00000003;
+pub const SFCR_DMDIS: u32 = 0x00000004;
+pub const SFCR_FS_MASK: u32 = 0x00000038;
+pub const SFCR_FEIE: u32 = 0x00000080;
+pub const SFCR_RESET: u32 = 0x00000021;
+
+/// Returns the shift to apply to the ISR_* flags for the given stream.
+/// Streams 0-3 live in LISR/LIFCR, 4-7 in HISR/HIFCR.
+pub fn stream_flag_shift(stream: usize) -> u32 {
+    [0, 6, 16, 22][stream & 3]
+}
@@ -0,0 +1,583 @@
+use collections::string::String;
+use collections::string::ToString;
+
+mod flags;
+
+pub use self::flags::*;
+
+use rcc;
+use time;
+use Peripheral;
+use IRQType;
+use registers::*;
+
+// _ = reserved
+// r = ro
+// w = rw
+// W = wo
+// lisr     ____rrrrrr_r____rrrrrr_r
+// hisr     ____rrrrrr_r____rrrrrr_r
+// lifcr    ____WWWWWW_W____WWWWWW_W
+// hifcr    ____WWWWWW_W____WWWWWW_W
+// sxcr     ____wwwwwwwwww_wwwwwwwwwwwwwwwww
+// sxfcr    ________________________w_rrrwww
+
+#[repr(C)]
+pub struct DMAStreamRegisters {
+    control: Rw<u32>,
+    data_count: Rw<u32>,
+    peripheral_address: Rw<u32>,
+    memory0_address: Rw<u32>,
+    memory1_address: Rw<u32>,
+    fifo_control: Rw<u32>
+}
+
+impl DMAStreamRegisters {
+    /// Number of data items left to transfer.
+    pub fn remaining(&self) -> u16 {
+        self.data_count.read() as u16
+    }
+}
+
+#[repr(C)]
+pub struct DMARegisters {
+    low_interrupt_status: Ro<u32>,
+    high_interrupt_status: Ro<u32>,
+    low_interrupt_flag_clear: Wo<u32>,
+    high_interrupt_flag_clear: Wo<u32>,
+    pub streams: [DMAStreamRegisters; 8]
+}
+impl DMARegisters {
+    fn stream_status(&self, stream: usize) -> u32 {
+        let isr = if stream < 4 {
+            self.low_interrupt_status.read()
+        } else {
+            self.high_interrupt_status.read()
+        };
+        (isr >> stream_flag_shift(stream)) & ISR_MASK
+    }
+    fn clear_stream_status(&mut self, stream: usize, flags: u32) {
+        let value = (flags & ISR_MASK) << stream_flag_shift(stream);
+        if stream < 4 {
+            self.low_interrupt_flag_clear.write(value);
+        } else {
+            self.high_interrupt_flag_clear.write(value);
+        }
+    }
+}
+
+/// Called from the stream's ISR with the registered argument and the ISR_*
+/// flags that were raised (and already cleared).
+pub type StreamCallback = fn(usize, u32);
+
+struct StreamHandler {
+    dma: *mut DMARegisters,
+    callback: Option<StreamCallback>,
+    argument: usize
+}
+impl StreamHandler {
+    const fn new() -> StreamHandler {
+        StreamHandler {
+            dma: 0 as *mut DMARegisters,
+            callback: None,
+            argument: 0
+        }
+    }
+}
+
+static mut HANDLERS: [StreamHandler; 16] = [
+    StreamHandler::new(), StreamHandler::new(), StreamHandler::new(), StreamHandler::new(),
+    StreamHandler::new(), StreamHandler::new(), StreamHandler::new(), StreamHandler::new(),
+    StreamHandler::new(), StreamHandler::new(), StreamHandler::new(), StreamHandler::new(),
+    StreamHandler::new(), StreamHandler::new(), StreamHandler::new(), StreamHandler::new()
+];
+
+unsafe fn on_interrupt(id: usize) {
+    let handler = &HANDLERS[id];
+    if handler.dma.is_null() {
+        return;
+    }
+    let stream = id & 7;
+    let flags = (*handler.dma).stream_status(stream);
+    (*handler.dma).clear_stream_status(stream, flags);
+    if let Some(callback) = handler.callback {
+        callback(handler.argument, flags);
+    }
+}
+
+pub unsafe extern "C" fn dma1_stream0_handler() {
+    on_interrupt(0);
+}
+pub unsafe extern "C" fn dma1_stream1_handler() {
+    on_interrupt(1);
+}
+pub unsafe extern "C" fn dma1_stream2_handler() {
+    on_interrupt(2);
+}
+pub unsafe extern "C" fn dma1_stream3_handler() {
+    on_interrupt(3);
+}
+pub unsafe extern "C" fn dma1_stream4_handler() {
+    on_interrupt(4);
+}
+pub unsafe extern "C" fn dma1_stream5_handler() {
+    on_interrupt(5);
+}
+pub unsafe extern "C" fn dma1_stream6_handler() {
+    on_interrupt(6);
+}
+pub unsafe extern "C" fn dma1_stream7_handler() {
+    on_interrupt(7);
+}
+pub unsafe extern "C" fn dma2_stream0_handler() {
+    on_interrupt(8);
+}
+pub unsafe extern "C" fn dma2_stream1_handler() {
+    on_interrupt(9);
+}
+pub unsafe extern "C" fn dma2_stream2_handler() {
+    on_interrupt(10);
+}
+pub unsafe extern "C" fn dma2_stream3_handler() {
+    on_interrupt(11);
+}
+pub unsafe extern "C" fn dma2_stream4_handler() {
+    on_interrupt(12);
+}
+pub unsafe extern "C" fn dma2_stream5_handler() {
+    on_interrupt(13);
+}
+pub unsafe extern "C" fn dma2_stream6_handler() {
+    on_interrupt(14);
+}
+pub unsafe extern "C" fn dma2_stream7_handler() {
+    on_interrupt(15);
+}
+
+#[derive(Copy, Clone)]
+pub enum Channel {
+    Channel0 = 0,
+    Channel1 = 1,
+    Channel2 = 2,
+    Channel3 = 3,
+    Channel4 = 4,
+    Channel5 = 5,
+    Channel6 = 6,
+    Channel7 = 7,
+    Channel8 = 8
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Direction {
+    PeripheralToMemory = 0,
+    MemoryToPeripheral = 1,
+    MemoryToMemory = 2
+}
+
+#[derive(Copy, Clone)]
+pub enum DataSize {
+    Byte = 0,
+    HalfWord = 1,
+    Word = 2
+}
+
+#[derive(Copy, Clone)]
+pub enum Priority {
+    Low = 0,
+    Medium = 1,
+    High = 2,
+    VeryHigh = 3
+}
+
+/// Describes a transfer to run on a stream.
+///
+/// For `MemoryToMemory`, `peripheral` is the source address and `memory` the
+/// destination (only DMA2 can do this).
+pub struct Transfer {
+    pub direction: Direction,
+    pub peripheral: usize,
+    pub peripheral_size: DataSize,
+    pub peripheral_increment: bool,
+    pub memory: usize,
+    pub memory_size: DataSize,
+    pub memory_increment: bool,
+    pub count: u16,
+    pub circular: bool,
+    pub priority: Priority,
+    /// Enables transfer complete, half transfer and error interrupts.
+    pub interrupts: bool
+}
+
+/// NDTR value for `len` data items.
+pub fn transfer_count(len: usize) -> Result<u16, String> {
+    if len > 0xFFFF {
+        return Err("A DMA transfer is limited to 65535 items.".to_string());
+    }
+    Ok(len as u16)
+}
+
+/// Milliseconds a stream may take to move an item when no peripheral paces
+/// it, which is also the bound on acknowledging its disabling.
+const STALL_TIMEOUT: u32 = 1;
+
+pub struct DMAPeripheral {
+    pub base_address: *mut DMARegisters,
+    pub isr_id: IRQType,
+    pub clock: rcc::RCCPeripheral
+}
+
+pub struct DMAStreamPeripheral<'a> {
+    pub dma: &'a DMAPeripheral,
+    pub base_address: *mut DMAStreamRegisters,
+    pub channel: Channel,
+}
+unsafe impl<'a> Sync for DMAStreamPeripheral<'a> {}
+
+impl<'a> DMAStreamPeripheral<'a> {
+    /// Stream number (0 to 7) within its DMA controller.
+    pub fn index(&self) -> usize {
+        let offset = (self.base_address as usize) - (self.dma.base_address as usize);
+        (offset - 0x10) / 0x18
+    }
+
+    fn is_dma2(&self) -> bool {
+        (self.dma.clock.clock as u8) == (rcc::Clock::DMA2 as u8)
+    }
+
+    fn stream(&self) -> &mut DMAStreamRegisters {
+        unsafe { &mut *self.base_address }
+    }
+
+    /// Interrupt line of this stream.
+    pub fn irq(&self) -> IRQType {
+        const DMA1: [IRQType; 8] = [
+            IRQType::DMA1_Stream0, IRQType::DMA1_Stream1, IRQType::DMA1_Stream2, IRQType::DMA1_Stream3,
+            IRQType::DMA1_Stream4, IRQType::DMA1_Stream5, IRQType::DMA1_Stream6, IRQType::DMA1_Stream7
+        ];
+        const DMA2: [IRQType; 8] = [
+            IRQType::DMA2_Stream0, IRQType::DMA2_Stream1, IRQType::DMA2_Stream2, IRQType::DMA2_Stream3,
+            IRQType::DMA2_Stream4, IRQType::DMA2_Stream5, IRQType::DMA2_Stream6, IRQType::DMA2_Stream7
+        ];
+        if self.is_dma2() {
+            DMA2[self.index()]
+        } else {
+            DMA1[self.index()]
+        }
+    }
+
+    /// Routes this stream's interrupt to `callback` and enables it in the NVIC.
+    pub fn attach(&self, callback: StreamCallback, argument: usize) {
+        let id = self.index() + if self.is_dma2() { 8 } else { 0 };
+        let irq = self.irq();
+
+        irq.disable();
+        unsafe {
+            HANDLERS[id] = StreamHandler {
+                dma: self.dma.base_address,
+                callback: Some(callback),
+                argument: argument
+            };
+        }
+        irq.enable();
+    }
+
+    /// Disables this stream's interrupt and forgets its callback.
+    pub fn detach(&self) {
+        let id = self.index() + if self.is_dma2() { 8 } else { 0 };
+
+        self.irq().disable();
+        unsafe {
+            HANDLERS[id] = StreamHandler::new();
+        }
+    }
+
+    /// Returns the ISR_* flags raised for this stream.
+    pub fn status(&self) -> u32 {
+        unsafe { (*self.dma.base_address).stream_status(self.index()) }
+    }
+
+    /// Clears the given ISR_* flags for this stream.
+    pub fn clear_status(&self, flags: u32) {
+        unsafe { (*self.dma.base_address).clear_stream_status(self.index(), flags) }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        (self.stream().control.read() & SCR_EN) == SCR_EN
+    }
+
+    /// Number of data items left to transfer.
+    pub fn remaining(&self) -> u16 {
+        self.stream().remaining()
+    }
+
+    /// Disables the stream and waits for the hardware to acknowledge it.
+    pub fn stop(&self) -> Result<(), String> {
+        self.stream().control.update(0, SCR_EN);
+
+        if !time::wait_for(STALL_TIMEOUT, || !self.is_enabled()) {
+            return Err("Failed to disable the DMA stream.".to_string());
+        }
+        Ok(())
+    }
+
+    /// Programs and enables a transfer.
+    pub fn start(&self, transfer: &Transfer) -> Result<(), String> {
+        if transfer.count == 0 {
+            return Err("Cannot start an empty transfer.".to_string());
+        }
+        if transfer.direction == Direction::MemoryToMemory {
+            if !self.is_dma2() {
+                return Err("Only DMA2 can do memory-to-memory transfers.".to_string());
+            }
+            if transfer.circular {
+                return Err("Memory-to-memory transfers cannot be circular.".to_string());
+            }
+        }
+
+        if let Err(msg) = self.stop() {
+            return Err(msg)
+        }
+        self.clear_status(ISR_MASK);
+
+        let mut cr = ((transfer.direction as u32) << SCR_DIR_SHIFT) |
+                     ((transfer.peripheral_size as u32) << SCR_PSIZE_SHIFT) |
+                     ((transfer.memory_size as u32) << SCR_MSIZE_SHIFT) |
+                     ((transfer.priority as u32) << SCR_PL_SHIFT);
+        if transfer.peripheral_increment {
+            cr |= SCR_PINC;
+        }
+        if transfer.memory_increment {
+            cr |= SCR_MINC;
+        }
+        if transfer.circular {
+            cr |= SCR_CIRC;
+        }
+        if transfer.interrupts {
+            cr |= SCR_TCIE | SCR_HTIE | SCR_TEIE | SCR_DMEIE;
+        }
+
+        let stream = self.stream();
+        stream.peripheral_address.write(transfer.peripheral as u32);
+        stream.memory0_address.write(transfer.memory as u32);
+        stream.data_count.write(transfer.count as u32);
+        if transfer.direction == Direction::MemoryToMemory {
+            // direct mode is not allowed for memory to memory
+            stream.fifo_control.write(SFCR_DMDIS | 0x3);
+        } else {
+            stream.fifo_control.write(SFCR_RESET);
+        }
+        stream.control.update(
+            cr,
+            SCR_DIR_MASK | SCR_PSIZE_MASK | SCR_MSIZE_MASK | SCR_PL_MASK | SCR_PINC | SCR_MINC |
+            SCR_CIRC | SCR_TCIE | SCR_HTIE | SCR_TEIE | SCR_DMEIE | SCR_DBM | SCR_CT | SCR_PFCTRL |
+            SCR_PBURST_MASK | SCR_MBURST_MASK
+        );
+        stream.control.update(SCR_EN, SCR_EN);
+
+        Ok(())
+    }
+
+    /// Busy-waits until the current (non circular) transfer completes. Fails
+    /// if no item is transferred for `timeout_ms`, the stream is left running.
+    pub fn wait(&self, timeout_ms: u32) -> Result<(), String> {
+        loop {
+            let remaining = self.remaining();
+            let moved = time::wait_for(timeout_ms, || {
+                ((self.status() & (ISR_TCIF | ISR_TEIF | ISR_DMEIF)) != 0) || (self.remaining() != remaining)
+            });
+            let status = self.status();
+            if (status & (ISR_TEIF | ISR_DMEIF)) != 0 {
+                self.clear_status(ISR_MASK);
+                return Err("DMA transfer error.".to_string());
+            }
+            if (status & ISR_TCIF) == ISR_TCIF {
+                self.clear_status(ISR_TCIF | ISR_HTIF);
+                return Ok(());
+            }
+            if !moved {
+                return Err("DMA transfer timeout.".to_string());
+            }
+        }
+    }
+
+    /// Sends `src` to a peripheral data register.
+    ///
+    /// The transfer outlives the borrow: `src` must stay valid and unchanged
+    /// until it completes or the stream is stopped.
+    pub unsafe fn memory_to_peripheral(&self, src: &[u8], peripheral: usize) -> Result<(), String> {
+        let count = match transfer_count(src.len()) {
+            Ok(count) => count,
+            Err(msg) => return Err(msg)
+        };
+        self.start(&Transfer {
+            direction: Direction::MemoryToPeripheral,
+            peripheral: peripheral,
+            peripheral_size: DataSize::Byte,
+            peripheral_increment: false,
+            memory: src.as_ptr() as usize,
+            memory_size: DataSize::Byte,
+            memory_increment: true,
+            count: count,
+            circular: false,
+            priority: Priority::Medium,
+            interrupts: false
+        })
+    }
+
+    /// Fills `dest` from a peripheral data register.
+    ///
+    /// The transfer outlives the borrow: `dest` must stay valid and must not
+    /// be accessed until it completes or the stream is stopped.
+    pub unsafe fn peripheral_to_memory(&self, peripheral: usize, dest: &mut [u8], circular: bool) -> Result<(), String> {
+        let count = match transfer_count(dest.len()) {
+            Ok(count) => count,
+            Err(msg) => return Err(msg)
+        };
+        self.start(&Transfer {
+            direction: Direction::PeripheralToMemory,
+            peripheral: peripheral,
+            peripheral_size: DataSize::Byte,
+            peripheral_increment: false,
+            memory: dest.as_mut_ptr() as usize,
+            memory_size: DataSize::Byte,
+            memory_increment: true,
+            count: count,
+            circular: circular,
+            priority: Priority::High,
+            interrupts: false
+        })
+    }
+
+    /// Copies `src` into `dest` and waits for completion.
+    pub fn memory_to_memory(&self, src: &[u8], dest: &mut [u8]) -> Result<(), String> {
+        if src.len() != dest.len() {
+            return Err("Source and destination lengths differ.".to_string());
+        }
+        let count = match transfer_count(src.len()) {
+            Ok(count) => count,
+            Err(msg) => return Err(msg)
+        };
+        let res = self.start(&Transfer {
+            direction: Direction::MemoryToMemory,
+            peripheral: src.as_ptr() as usize,
+            peripheral_size: DataSize::Byte,
+            peripheral_increment: true,
+            memory: dest.as_mut_ptr() as usize,
+            memory_size: DataSize::Byte,
+            memory_increment: true,
+            count: count,
+            circular: false,
+            priority: Priority::Low,
+            interrupts: false
+        });
+        if let Err(msg) = res {
+            return Err(msg)
+        }
+        let res = self.wait(STALL_TIMEOUT);
+        if res.is_err() {
+            // `dest` must not be written once the borrow ends
+            let _ = self.stop();
+        }
+        res
+    }
+}
+
+impl<'a> Peripheral for DMAStreamPeripheral<'a> {
+    fn init(&self) -> Result<(), String> {
+        if (self.channel as u32) > 7 {
+            return Err("Invalid DMA channel".to_string())
+        }
+
+        init_peripheral![Some(&self.dma.clock)];
+
+        if let Err(msg) = self.stop() {
+            return Err(msg)
+        }
+        self.clear_status(ISR_MASK);
+        self.stream().control.update(
+            (self.channel as u32) << SCR_CHSEL_SHIFT,
+            SCR_CHSEL_MASK
+        );
+
+        Ok(())
+    }
+    fn deinit(&self) -> Result<(), String> {
+        if let Err(msg) = self.stop() {
+            return Err(msg)
+        }
+
+        let stream = self.stream();
+        stream.control.write(0);
+        stream.data_count.write(0);
+        stream.peripheral_address.write(0);
+        stream.memory0_address.write(0);
+        stream.memory1_address.write(0);
+        stream.fifo_control.write(SFCR_RESET);
+        self.clear_status(ISR_MASK);
+        self.detach();
+
+        // the DMA clock is shared by all the streams so we leave it on.
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LISR and stream 0 CR in the fake DMA block
+    const LISR: usize = 0;
+    const S0CR: usize = 4;
+
+    fn stream<'a>(dma: &'a DMAPeripheral, regs: &mut [u32; 52]) -> DMAStreamPeripheral<'a> {
+        DMAStreamPeripheral {
+            dma: dma,
+            base_address: unsafe { regs.as_mut_ptr().offset(S0CR as isize) } as *mut DMAStreamRegisters,
+            channel: Channel::Channel0
+        }
+    }
+
+    fn controller(regs: &mut [u32; 52], rcc: &mut [u32; 36]) -> DMAPeripheral {
+        DMAPeripheral {
+            base_address: regs.as_mut_ptr() as *mut DMARegisters,
+            isr_id: IRQType::DMA1_Stream0,
+            clock: rcc::RCCPeripheral {
+                rcc: rcc.as_mut_ptr() as *mut rcc::RCCRegisters,
+                clock: rcc::Clock::DMA1
+            }
+        }
+    }
+
+    #[test]
+    fn transfer_count_is_16_bits() {
+        assert_eq!(transfer_count(0xFFFF), Ok(0xFFFF));
+        assert!(transfer_count(0x10000).is_err());
+    }
+
+    #[test]
+    fn wait_reports_completion_errors_and_stalls() {
+        let mut regs = [0; 52];
+        let mut rcc = [0; 36];
+        let dma = controller(&mut regs, &mut rcc);
+        let stream = stream(&dma, &mut regs);
+
+        regs[LISR] = ISR_TCIF;
+        assert_eq!(stream.wait(1), Ok(()));
+        regs[LISR] = ISR_TEIF;
+        assert_eq!(stream.wait(1), Err("DMA transfer error.".to_string()));
+        regs[LISR] = 0;
+        assert_eq!(stream.wait(1), Err("DMA transfer timeout.".to_string()));
+    }
+
+    #[test]
+    fn stop_clears_the_enable_bit() {
+        let mut regs = [0; 52];
+        let mut rcc = [0; 36];
+        let dma = controller(&mut regs, &mut rcc);
+        let stream = stream(&dma, &mut regs);
+
+        regs[S0CR] = SCR_EN;
+        assert_eq!(stream.stop(), Ok(()));
+        assert_eq!(regs[S0CR] & SCR_EN, 0);
+    }
+}
@@ -0,0 +1,347 @@
+use collections::string::String;
+use collections::string::ToString;
+
+use rcc;
+use IRQType;
+use Peripheral;
+use gpio::PinPeripheral;
+use registers::*;
+
+#[repr(C)]
+pub struct ExtiRegisters {
+    interrupt_mask: Rw<u32>,
+    event_mask: Rw<u32>,
+    rising_trigger: Rw<u32>,
+    falling_trigger: Rw<u32>,
+    software_interrupt: Rw<u32>,
+    pending: Rw<u32>
+}
+
+#[repr(C)]
+pub struct SyscfgRegisters {
+    memory_remap: Rw<u32>,
+    peripheral_mode: Rw<u32>,
+    exti_config: [Rw<u32>; 4],
+    reserved0: u32,
+    reserved1: u32,
+    compensation_cell: Rw<u32>
+}
+
+extern {
+    pub fn exti_get() -> &mut ExtiRegisters;
+    pub fn syscfg_get() -> &mut SyscfgRegisters;
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Edge {
+    Rising,
+    Falling,
+    Both
+}
+
+/// Called from the EXTI ISR with the registered argument and the line number.
+pub type ExtiCallback = fn(usize, u32);
+
+struct ExtiHandler {
+    /// Port owning the line, 0 for GPIOA.
+    port: Option<usize>,
+    callback: Option<ExtiCallback>,
+    argument: usize
+}
+impl ExtiHandler {
+    const fn new() -> ExtiHandler {
+        ExtiHandler {
+            port: None,
+            callback: None,
+            argument: 0
+        }
+    }
+}
+
+// indexed by line number
+static mut HANDLERS: [ExtiHandler; 16] = [
+    ExtiHandler::new(), ExtiHandler::new(), ExtiHandler::new(), ExtiHandler::new(),
+    ExtiHandler::new(), ExtiHandler::new(), ExtiHandler::new(), ExtiHandler::new(),
+    ExtiHandler::new(), ExtiHandler::new(), ExtiHandler::new(), ExtiHandler::new(),
+    ExtiHandler::new(), ExtiHandler::new(), ExtiHandler::new(), ExtiHandler::new()
+];
+
+/// Interrupt line EXTI `line` is routed to.
+pub fn line_irq(line: u32) -> IRQType {
+    match line {
+        0 => IRQType::EXTI0,
+        1 => IRQType::EXTI1,
+        2 => IRQType::EXTI2,
+        3 => IRQType::EXTI3,
+        4 => IRQType::EXTI4,
+        5...9 => IRQType::EXTI9_5,
+        _ => IRQType::EXTI15_10
+    }
+}
+
+/// Bits of lines `first` to `last`.
+fn line_mask(first: u32, last: u32) -> u32 {
+    ((1 << (last + 1)) - 1) & !((1 << first) - 1)
+}
+
+unsafe fn on_interrupt(first: u32, last: u32) {
+    dispatch(exti_get(), first, last);
+}
+
+/// Clears the pending unmasked lines among `first` to `last` and calls their
+/// callbacks.
+unsafe fn dispatch(exti: &mut ExtiRegisters, first: u32, last: u32) {
+    let pending = exti.pending.read() & exti.interrupt_mask.read() & line_mask(first, last);
+    // flags are cleared by writing 1
+    exti.pending.write(pending);
+    for line in first..(last + 1) {
+        if (pending & (1 << line)) != 0 {
+            let handler = &HANDLERS[line as usize];
+            if let Some(callback) = handler.callback {
+                callback(handler.argument, line);
+            }
+        }
+    }
+}
+
+pub unsafe extern "C" fn exti0_handler() {
+    on_interrupt(0, 0);
+}
+pub unsafe extern "C" fn exti1_handler() {
+    on_interrupt(1, 1);
+}
+pub unsafe extern "C" fn exti2_handler() {
+    on_interrupt(2, 2);
+}
+pub unsafe extern "C" fn exti3_handler() {
+    on_interrupt(3, 3);
+}
+pub unsafe extern "C" fn exti4_handler() {
+    on_interrupt(4, 4);
+}
+pub unsafe extern "C" fn exti9_5_handler() {
+    on_interrupt(5, 9);
+}
+pub unsafe extern "C" fn exti15_10_handler() {
+    on_interrupt(10, 15);
+}
+
+fn port_of(pin: &PinPeripheral) -> Result<usize, String> {
+    if 15 < pin.pin {
+        return Err("Invalid pin number".to_string())
+    }
+    match pin.port.index() {
+        Some(index) => Ok(index),
+        None => Err("Invalid GPIO port clock".to_string())
+    }
+}
+
+/// Registers `callback` on `line` for `port`, unless another port owns it.
+fn claim(line: u32, port: usize, callback: ExtiCallback, argument: usize) -> bool {
+    ::critical_section(|| unsafe {
+        let handler = &mut HANDLERS[line as usize];
+        match handler.port {
+            Some(owner) if owner != port => false,
+            _ => {
+                *handler = ExtiHandler {
+                    port: Some(port),
+                    callback: Some(callback),
+                    argument: argument
+                };
+                true
+            }
+        }
+    })
+}
+
+fn is_owner(line: u32, port: usize) -> bool {
+    ::critical_section(|| unsafe { HANDLERS[line as usize].port == Some(port) })
+}
+
+/// Returns the line of `pin` if it is attached to it.
+fn attached_line(pin: &PinPeripheral) -> Result<u32, String> {
+    let port = match port_of(pin) {
+        Ok(port) => port,
+        Err(msg) => return Err(msg)
+    };
+    if !is_owner(pin.pin, port) {
+        return Err("This EXTI line is not attached to this pin.".to_string());
+    }
+    Ok(pin.pin)
+}
+
+/// Calls `callback` on each `edge` of `pin`. The pin must be initialized.
+/// Fails if the line is already used by the same pin number of another port.
+pub fn attach(pin: &PinPeripheral, edge: Edge, callback: ExtiCallback, argument: usize) -> Result<(), String> {
+    let port = match port_of(pin) {
+        Ok(port) => port,
+        Err(msg) => return Err(msg)
+    };
+    let line = pin.pin;
+    let mask = 1 << line;
+
+    let syscfg_clock = rcc::RCCPeripheral {
+        rcc: unsafe { rcc::rcc_get() },
+        clock: rcc::Clock::SYSCFG
+    };
+    if let Err(msg) = syscfg_clock.init() {
+        return Err(msg);
+    }
+
+    if !claim(line, port, callback, argument) {
+        return Err("This EXTI line is already used by another port.".to_string());
+    }
+
+    let exti = unsafe { exti_get() };
+    let syscfg = unsafe { syscfg_get() };
+    exti.interrupt_mask.update(0, mask);
+
+    let shift = (line & 3) * 4;
+    syscfg.exti_config[(line >> 2) as usize].update((port as u32) << shift, 0xF << shift);
+
+    let (rising, falling) = match edge {
+        Edge::Rising => (mask, 0),
+        Edge::Falling => (0, mask),
+        Edge::Both => (mask, mask)
+    };
+    exti.rising_trigger.update(rising, mask);
+    exti.falling_trigger.update(falling, mask);
+    exti.pending.write(mask);
+    exti.interrupt_mask.update(mask, mask);
+
+    line_irq(line).enable();
+    Ok(())
+}
+
+/// Stops the interrupts of `pin` and releases its line. The NVIC line is
+/// left enabled when shared with other pins.
+pub fn detach(pin: &PinPeripheral) -> Result<(), String> {
+    let port = match port_of(pin) {
+        Ok(port) => port,
+        Err(msg) => return Err(msg)
+    };
+    let line = pin.pin;
+    let mask = 1 << line;
+
+    let exti = unsafe { exti_get() };
+    let owned = ::critical_section(|| unsafe {
+        if HANDLERS[line as usize].port != Some(port) {
+            return false;
+        }
+        exti.interrupt_mask.update(0, mask);
+        exti.rising_trigger.update(0, mask);
+        exti.falling_trigger.update(0, mask);
+        exti.pending.write(mask);
+        HANDLERS[line as usize] = ExtiHandler::new();
+        true
+    });
+    if !owned {
+        return Err("This EXTI line is not attached to this pin.".to_string());
+    }
+
+    if line < 5 {
+        line_irq(line).disable();
+    }
+    Ok(())
+}
+
+/// Raises the interrupt of `pin`'s line by software. The line must be
+/// attached to `pin`.
+pub fn trigger(pin: &PinPeripheral) -> Result<(), String> {
+    let line = match attached_line(pin) {
+        Ok(line) => line,
+        Err(msg) => return Err(msg)
+    };
+    let exti = unsafe { exti_get() };
+    exti.software_interrupt.write(1 << line);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gpio::{Frequency, Mode, PortPeripheral, PortRegisters, PullSide};
+
+    // register indexes in the fake EXTI block
+    const IMR: usize = 0;
+    const PR: usize = 5;
+
+    static mut FIRED: u32 = 0;
+
+    fn record(argument: usize, line: u32) {
+        assert_eq!(argument, line as usize);
+        unsafe {
+            FIRED |= 1 << line;
+        }
+    }
+
+    fn port(clock: rcc::Clock) -> PortPeripheral {
+        PortPeripheral {
+            base_address: 0 as *mut PortRegisters,
+            clock: rcc::RCCPeripheral {
+                rcc: 0 as *mut rcc::RCCRegisters,
+                clock: clock
+            }
+        }
+    }
+
+    fn pin<'a>(port: &'a PortPeripheral, pin: u32) -> PinPeripheral<'a> {
+        PinPeripheral {
+            port: port,
+            pin: pin,
+            mode: Mode::In,
+            speed: Frequency::F2MHz,
+            pull_side: PullSide::None
+        }
+    }
+
+    #[test]
+    fn line_masks_cover_the_shared_vectors() {
+        assert_eq!(line_mask(0, 0), 0x0001);
+        assert_eq!(line_mask(4, 4), 0x0010);
+        assert_eq!(line_mask(5, 9), 0x03E0);
+        assert_eq!(line_mask(10, 15), 0xFC00);
+    }
+
+    #[test]
+    fn dispatch_calls_the_pending_unmasked_lines_of_its_vector() {
+        for line in 5..10 {
+            assert!(claim(line, 2, record, line as usize));
+        }
+        let mut registers = [0u32; 6];
+        // 4 and 10 belong to other vectors, 8 is masked
+        registers[IMR] = 0xFFFF & !(1 << 8);
+        registers[PR] = (1 << 4) | (1 << 6) | (1 << 8) | (1 << 9) | (1 << 10);
+        unsafe {
+            dispatch(&mut *(registers.as_mut_ptr() as *mut ExtiRegisters), 5, 9);
+            assert_eq!(FIRED, (1 << 6) | (1 << 9));
+        }
+        // only the dispatched lines are cleared, by writing 1
+        assert_eq!(registers[PR], (1 << 6) | (1 << 9));
+    }
+
+    #[test]
+    fn a_line_belongs_to_one_port() {
+        assert!(claim(12, 0, record, 12));
+        // the owner may replace its callback, another port may not
+        assert!(claim(12, 0, record, 12));
+        assert!(!claim(12, 1, record, 12));
+        assert!(is_owner(12, 0));
+        assert!(!is_owner(12, 1));
+        unsafe {
+            HANDLERS[12] = ExtiHandler::new();
+        }
+        assert!(claim(12, 1, record, 12));
+        assert!(is_owner(12, 1));
+    }
+
+    #[test]
+    fn only_the_attached_pin_triggers_its_line() {
+        let gpioa = port(rcc::Clock::GPIOA);
+        let gpiob = port(rcc::Clock::GPIOB);
+        assert!(claim(3, 0, record, 3));
+        assert_eq!(attached_line(&pin(&gpioa, 3)), Ok(3));
+        assert_eq!(attached_line(&pin(&gpiob, 3)), Err("This EXTI line is not attached to this pin.".to_string()));
+        assert_eq!(attached_line(&pin(&gpioa, 16)), Err("Invalid pin number".to_string()));
+    }
+}
@@ -0,0 +1,665 @@
+//! Key/value EEPROM emulation on top of two or more flash pages.
+//!
+//! One page is active at a time. Records are appended to it and the last
+//! record written for a key holds its value. When the active page is full, the
+//! latest value of each key is moved to the next page of the ring and the old
+//! page is erased.
+//!
+//! Page layout (half-words):
+//!
+//! ```text
+//! | status | generation | value | key | commit | value | key | commit | ...
+//! ```
+//!
+//! Flash bits can only be cleared so the status goes from ERASED to RECEIVE
+//! then VALID without erase. A record only counts once its commit half-word
+//! has been programmed, so a power cut in the middle of a write leaves the
+//! previous value in place. `Eeprom::init` completes or rolls back any
+//! interrupted page transfer.
+
+use core::intrinsics;
+
+use super::{FlashRegisters, Parallelism, VoltageRange, sector_range};
+
+pub const PAGE_ERASED: u16 = 0xFFFF;
+pub const PAGE_RECEIVE: u16 = 0xEEEE;
+pub const PAGE_VALID: u16 = 0x0000;
+
+const HEADER_SIZE: usize = 4;
+const RECORD_SIZE: usize = 6;
+const RECORD_COMMITTED: u16 = 0x0000;
+const BLANK: u16 = 0xFFFF;
+
+/// Storage used by the EEPROM emulation. Offsets are in bytes from the start
+/// of the page and always half-word aligned.
+pub trait EepromBackend {
+    fn page_size(&self) -> usize;
+    fn page_count(&self) -> usize;
+    fn read(&self, page: usize, offset: usize) -> u16;
+    fn program(&mut self, page: usize, offset: usize, value: u16) -> Result<(), &'static str>;
+    fn erase(&mut self, page: usize) -> Result<(), &'static str>;
+}
+
+/// Backend storing the EEPROM in same-sized flash sectors.
+pub struct SectorBackend<'a> {
+    flash: &'a mut FlashRegisters,
+    sectors: &'a [u8],
+    size: usize,
+    /// Widest erase and program size allowed by the supply voltage.
+    psize: Parallelism
+}
+impl<'a> SectorBackend<'a> {
+    /// Unlocks the flash and checks the sectors can be used together.
+    /// `voltage` is the board supply range, it bounds the parallelism.
+    pub fn new(flash: &'a mut FlashRegisters, sectors: &'a [u8], voltage: VoltageRange) -> Result<SectorBackend<'a>, &'static str> {
+        if sectors.len() < 2 {
+            return Err("The EEPROM emulation needs at least two sectors.");
+        }
+        let size = match sector_range(sectors[0]) {
+            Some((_, size)) => size,
+            None => return Err("Invalid flash sector.")
+        };
+        for (i, sector) in sectors.iter().enumerate() {
+            match sector_range(*sector) {
+                Some((_, s)) if s == size => {},
+                Some(_) => return Err("All the EEPROM sectors must have the same size."),
+                None => return Err("Invalid flash sector.")
+            }
+            // two pages in one sector would erase each other
+            if sectors[..i].contains(sector) {
+                return Err("The EEPROM sectors must be distinct.");
+            }
+        }
+        if let Err(msg) = flash.unlock() {
+            return Err(msg);
+        }
+
+        Ok(SectorBackend {
+            flash: flash,
+            sectors: sectors,
+            size: size,
+            psize: Parallelism::from_voltage(voltage)
+        })
+    }
+
+    fn address(&self, page: usize, offset: usize) -> Result<usize, &'static str> {
+        if self.sectors.len() <= page {
+            return Err("Invalid EEPROM page.");
+        }
+        match sector_range(self.sectors[page]) {
+            Some((start, _)) => Ok(start + offset),
+            None => Err("Invalid flash sector.")
+        }
+    }
+}
+impl<'a> EepromBackend for SectorBackend<'a> {
+    fn page_size(&self) -> usize {
+        self.size
+    }
+    fn page_count(&self) -> usize {
+        self.sectors.len()
+    }
+    fn read(&self, page: usize, offset: usize) -> u16 {
+        match self.address(page, offset) {
+            Ok(address) => unsafe { intrinsics::volatile_load(address as *const u16) },
+            // reads as erased, the following program or erase reports it
+            Err(_) => BLANK
+        }
+    }
+    fn program(&mut self, page: usize, offset: usize, value: u16) -> Result<(), &'static str> {
+        let address = match self.address(page, offset) {
+            Ok(address) => address,
+            Err(msg) => return Err(msg)
+        };
+        // half-words are programmed in one go unless x8 is all the supply allows
+        let psize = if self.psize == Parallelism::X8 { Parallelism::X8 } else { Parallelism::X16 };
+        self.flash.program(address, &[value as u8, (value >> 8) as u8], psize)
+    }
+    fn erase(&mut self, page: usize) -> Result<(), &'static str> {
+        if self.sectors.len() <= page {
+            return Err("Invalid EEPROM page.");
+        }
+        let sector = self.sectors[page];
+        self.flash.erase_sector(sector, self.psize)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum PageState {
+    Erased,
+    Receive,
+    Valid,
+    Invalid
+}
+
+fn page_state(status: u16) -> PageState {
+    match status {
+        PAGE_ERASED => PageState::Erased,
+        PAGE_VALID => PageState::Valid,
+        // a RECEIVE page which was being marked VALID when power was lost
+        s if (s & PAGE_RECEIVE) == s => PageState::Receive,
+        _ => PageState::Invalid
+    }
+}
+
+/// `a` is newer than `b`, with wrapping.
+fn is_newer(a: u16, b: u16) -> bool {
+    (a.wrapping_sub(b) as i16) > 0
+}
+
+pub struct Eeprom<B: EepromBackend> {
+    backend: B,
+    active: usize,
+    next: usize,
+    generation: u16
+}
+
+impl<B: EepromBackend> Eeprom<B> {
+    /// Mounts the EEPROM, recovering from any interrupted operation. Blank or
+    /// unreadable storage is formatted.
+    pub fn init(backend: B) -> Result<Eeprom<B>, &'static str> {
+        if backend.page_count() < 2 {
+            return Err("The EEPROM emulation needs at least two pages.");
+        }
+        if backend.page_size() < HEADER_SIZE + 2 * RECORD_SIZE {
+            return Err("The EEPROM pages are too small.");
+        }
+
+        let mut eeprom = Eeprom {
+            backend: backend,
+            active: 0,
+            next: HEADER_SIZE,
+            generation: 0
+        };
+
+        let mut valid: Option<usize> = None;
+        let mut receive: Option<usize> = None;
+        for page in 0..eeprom.backend.page_count() {
+            let generation = eeprom.backend.read(page, 2);
+            let slot = match page_state(eeprom.backend.read(page, 0)) {
+                PageState::Valid => &mut valid,
+                PageState::Receive => &mut receive,
+                PageState::Erased => continue,
+                PageState::Invalid => {
+                    if let Err(msg) = eeprom.backend.erase(page) {
+                        return Err(msg);
+                    }
+                    continue;
+                }
+            };
+            // keep the newest page of each state and drop the other one
+            let drop = match *slot {
+                Some(other) if is_newer(eeprom.backend.read(other, 2), generation) => page,
+                Some(other) => {
+                    *slot = Some(page);
+                    other
+                }
+                None => {
+                    *slot = Some(page);
+                    continue;
+                }
+            };
+            if let Err(msg) = eeprom.backend.erase(drop) {
+                return Err(msg);
+            }
+        }
+
+        match (valid, receive) {
+            (Some(valid), Some(receive)) => {
+                if is_newer(eeprom.backend.read(receive, 2), eeprom.backend.read(valid, 2)) {
+                    // a page transfer was interrupted, complete it
+                    eeprom.mount(valid);
+                    let next = eeprom.scan_next(receive);
+                    let res = if eeprom.transfer_fits(receive, next) {
+                        eeprom.complete_transfer(receive, next)
+                    } else {
+                        // the write which started it did not fit, drop it
+                        eeprom.backend.erase(receive)
+                    };
+                    if let Err(msg) = res {
+                        return Err(msg);
+                    }
+                } else {
+                    // stale page from a previous cycle
+                    if let Err(msg) = eeprom.backend.erase(receive) {
+                        return Err(msg);
+                    }
+                    eeprom.mount(valid);
+                }
+            },
+            (Some(valid), None) => eeprom.mount(valid),
+            (None, Some(receive)) => {
+                // nothing else holds data, keep what was transferred
+                if let Err(msg) = eeprom.backend.program(receive, 0, PAGE_VALID) {
+                    return Err(msg);
+                }
+                eeprom.mount(receive);
+            },
+            (None, None) => {
+                if let Err(msg) = eeprom.format() {
+                    return Err(msg);
+                }
+            }
+        }
+
+        Ok(eeprom)
+    }
+
+    /// Erases every page and starts with an empty EEPROM.
+    pub fn format(&mut self) -> Result<(), &'static str> {
+        for page in 0..self.backend.page_count() {
+            if let Err(msg) = self.backend.erase(page) {
+                return Err(msg);
+            }
+        }
+        if let Err(msg) = self.backend.program(0, 2, 0) {
+            return Err(msg);
+        }
+        if let Err(msg) = self.backend.program(0, 0, PAGE_VALID) {
+            return Err(msg);
+        }
+        self.mount(0);
+        Ok(())
+    }
+
+    /// Returns the value last written for `key`.
+    pub fn read(&self, key: u16) -> Option<u16> {
+        self.find(self.active, self.next, key)
+    }
+
+    /// Stores `value` for `key`. `0xFFFF` is not a valid key.
+    pub fn write(&mut self, key: u16, value: u16) -> Result<(), &'static str> {
+        if key == BLANK {
+            return Err("0xFFFF is not a valid EEPROM key.");
+        }
+        if self.read(key) == Some(value) {
+            return Ok(());
+        }
+
+        if self.next + RECORD_SIZE <= self.backend.page_size() {
+            let (page, offset) = (self.active, self.next);
+            // skip this slot whatever happens, it may be half written
+            self.next += RECORD_SIZE;
+            return self.write_record(page, offset, key, value);
+        }
+
+        // page full: move to the next one, new record first
+        let target = (self.active + 1) % self.backend.page_count();
+        if let Err(msg) = self.start_transfer(target) {
+            return Err(msg);
+        }
+        let res = match self.write_record(target, HEADER_SIZE, key, value) {
+            Ok(_) => self.complete_transfer(target, HEADER_SIZE + RECORD_SIZE),
+            Err(msg) => Err(msg)
+        };
+        if res.is_err() && (self.active != target) {
+            // the active page still holds everything, drop the partial copy
+            let _ = self.backend.erase(target);
+        }
+        res
+    }
+
+    /// Gives the backend back.
+    pub fn release(self) -> B {
+        self.backend
+    }
+
+    fn mount(&mut self, page: usize) {
+        self.active = page;
+        self.generation = self.backend.read(page, 2);
+        self.next = self.scan_next(page);
+    }
+
+    /// Returns the offset following the last record that has been (even
+    /// partially) written in `page`.
+    fn scan_next(&self, page: usize) -> usize {
+        let mut next = HEADER_SIZE;
+        let mut offset = HEADER_SIZE;
+        while offset + RECORD_SIZE <= self.backend.page_size() {
+            for i in 0..(RECORD_SIZE / 2) {
+                if self.backend.read(page, offset + 2 * i) != BLANK {
+                    next = offset + RECORD_SIZE;
+                    break;
+                }
+            }
+            offset += RECORD_SIZE;
+        }
+        next
+    }
+
+    /// Looks for the latest committed record of `key` in `page`, before `end`.
+    fn find(&self, page: usize, end: usize, key: u16) -> Option<u16> {
+        let mut offset = end;
+        while offset > HEADER_SIZE {
+            offset -= RECORD_SIZE;
+            if (self.backend.read(page, offset + 2) == key) &&
+               (self.backend.read(page, offset + 4) == RECORD_COMMITTED) {
+                return Some(self.backend.read(page, offset));
+            }
+        }
+        None
+    }
+
+    fn write_record(&mut self, page: usize, offset: usize, key: u16, value: u16) -> Result<(), &'static str> {
+        if let Err(msg) = self.backend.program(page, offset, value) {
+            return Err(msg);
+        }
+        if let Err(msg) = self.backend.program(page, offset + 2, key) {
+            return Err(msg);
+        }
+        self.backend.program(page, offset + 4, RECORD_COMMITTED)
+    }
+
+    /// Prepares `target` to receive the active page content.
+    fn start_transfer(&mut self, target: usize) -> Result<(), &'static str> {
+        // a power cut during an erase can leave a page with a blank header
+        // but garbage in it, so check the whole page.
+        let mut blank = true;
+        let mut offset = 0;
+        while blank && (offset < self.backend.page_size()) {
+            blank = self.backend.read(target, offset) == BLANK;
+            offset += 2;
+        }
+        if !blank {
+            if let Err(msg) = self.backend.erase(target) {
+                return Err(msg);
+            }
+        }
+
+        let generation = self.generation.wrapping_add(1);
+        if let Err(msg) = self.backend.program(target, 2, generation) {
+            return Err(msg);
+        }
+        self.backend.program(target, 0, PAGE_RECEIVE)
+    }
+
+    /// Whether the record of the active page at `offset` is committed and
+    /// holds the latest value of its key.
+    fn is_latest(&self, offset: usize) -> bool {
+        let key = self.backend.read(self.active, offset + 2);
+        if (key == BLANK) || (self.backend.read(self.active, offset + 4) != RECORD_COMMITTED) {
+            return false;
+        }
+        let mut later = offset + RECORD_SIZE;
+        while later < self.next {
+            if (self.backend.read(self.active, later + 2) == key) &&
+               (self.backend.read(self.active, later + 4) == RECORD_COMMITTED) {
+                return false;
+            }
+            later += RECORD_SIZE;
+        }
+        true
+    }
+
+    /// Whether the keys of the active page that `target` does not hold yet
+    /// fit in it after `next`.
+    fn transfer_fits(&self, target: usize, next: usize) -> bool {
+        let mut end = next;
+        let mut offset = self.next;
+        while offset > HEADER_SIZE {
+            offset -= RECORD_SIZE;
+            if self.is_latest(offset) &&
+               self.find(target, next, self.backend.read(self.active, offset + 2)).is_none() {
+                end += RECORD_SIZE;
+            }
+        }
+        end <= self.backend.page_size()
+    }
+
+    /// Copies the latest value of each key of the active page that `target`
+    /// does not hold yet, then makes `target` the active page. `next` is the
+    /// first free offset in `target`.
+    fn complete_transfer(&mut self, target: usize, next: usize) -> Result<(), &'static str> {
+        if !self.transfer_fits(target, next) {
+            return Err("The EEPROM is full.");
+        }
+        let mut next = next;
+        let mut offset = self.next;
+
+        while offset > HEADER_SIZE {
+            offset -= RECORD_SIZE;
+            let key = self.backend.read(self.active, offset + 2);
+            if (key == BLANK) || (self.backend.read(self.active, offset + 4) != RECORD_COMMITTED) {
+                continue;
+            }
+            if self.find(target, next, key).is_some() {
+                continue;
+            }
+            let value = self.backend.read(self.active, offset);
+            let res = self.write_record(target, next, key, value);
+            next += RECORD_SIZE;
+            if let Err(msg) = res {
+                return Err(msg);
+            }
+        }
+
+        if let Err(msg) = self.backend.program(target, 0, PAGE_VALID) {
+            return Err(msg);
+        }
+        let previous = self.active;
+        self.mount(target);
+        self.backend.erase(previous)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE_SIZE: usize = HEADER_SIZE + 4 * RECORD_SIZE;
+    const PAGE_COUNT: usize = 3;
+
+    /// RAM flash which loses power after a given number of operations. The
+    /// interrupted operation is left half done.
+    struct RamBackend {
+        pages: [[u16; PAGE_SIZE / 2]; PAGE_COUNT],
+        operations_left: Option<usize>,
+        powered: bool
+    }
+    impl RamBackend {
+        fn new() -> RamBackend {
+            RamBackend {
+                pages: [[BLANK; PAGE_SIZE / 2]; PAGE_COUNT],
+                operations_left: None,
+                powered: true
+            }
+        }
+
+        fn cut_after(&mut self, operations: usize) {
+            self.operations_left = Some(operations);
+        }
+
+        fn power_on(&mut self) {
+            self.operations_left = None;
+            self.powered = true;
+        }
+
+        /// Whether the next operation completes.
+        fn operation(&mut self) -> Result<bool, &'static str> {
+            if !self.powered {
+                return Err("Power lost.");
+            }
+            match self.operations_left {
+                Some(0) => {
+                    self.powered = false;
+                    Ok(false)
+                },
+                Some(n) => {
+                    self.operations_left = Some(n - 1);
+                    Ok(true)
+                },
+                None => Ok(true)
+            }
+        }
+    }
+    impl<'a> EepromBackend for &'a mut RamBackend {
+        fn page_size(&self) -> usize {
+            PAGE_SIZE
+        }
+        fn page_count(&self) -> usize {
+            PAGE_COUNT
+        }
+        fn read(&self, page: usize, offset: usize) -> u16 {
+            self.pages[page][offset / 2]
+        }
+        fn program(&mut self, page: usize, offset: usize, value: u16) -> Result<(), &'static str> {
+            assert_eq!(offset & 1, 0);
+            match self.operation() {
+                Ok(true) => {
+                    // programming only clears bits
+                    self.pages[page][offset / 2] &= value;
+                    Ok(())
+                },
+                Ok(false) => {
+                    self.pages[page][offset / 2] &= value | 0xFF00;
+                    Err("Power lost.")
+                },
+                Err(msg) => Err(msg)
+            }
+        }
+        fn erase(&mut self, page: usize) -> Result<(), &'static str> {
+            let complete = match self.operation() {
+                Ok(complete) => complete,
+                Err(msg) => return Err(msg)
+            };
+            let words = if complete { PAGE_SIZE / 2 } else { PAGE_SIZE / 4 };
+            for i in 0..words {
+                self.pages[page][i] = BLANK;
+            }
+            if complete { Ok(()) } else { Err("Power lost.") }
+        }
+    }
+
+    const WRITES: [(u16, u16); 14] = [
+        (1, 10), (2, 20), (1, 11), (3, 30), (1, 12), (2, 21), (3, 31),
+        (1, 13), (2, 22), (1, 14), (3, 32), (2, 23), (1, 15), (3, 33)
+    ];
+
+    /// Replays `WRITES` from blank storage until the power is lost. Returns
+    /// the values reported as written and the write that was interrupted.
+    fn replay(backend: &mut RamBackend) -> ([Option<u16>; 4], Option<(u16, u16)>) {
+        let mut committed = [None; 4];
+        let mut eeprom = match Eeprom::init(backend) {
+            Ok(eeprom) => eeprom,
+            Err(_) => return (committed, None)
+        };
+        for &(key, value) in WRITES.iter() {
+            if eeprom.write(key, value).is_err() {
+                return (committed, Some((key, value)));
+            }
+            committed[key as usize] = Some(value);
+        }
+        (committed, None)
+    }
+
+    #[test]
+    fn eeprom_reads_back_writes() {
+        let mut backend = RamBackend::new();
+        let (committed, interrupted) = replay(&mut backend);
+        assert!(interrupted.is_none());
+        let eeprom = Eeprom::init(&mut backend).unwrap();
+        for key in 1..4 {
+            assert_eq!(eeprom.read(key as u16), committed[key]);
+        }
+        assert_eq!(eeprom.read(4), None);
+    }
+
+    #[test]
+    fn eeprom_rejects_blank_key() {
+        let mut backend = RamBackend::new();
+        let mut eeprom = Eeprom::init(&mut backend).unwrap();
+        assert!(eeprom.write(BLANK, 0).is_err());
+    }
+
+    #[test]
+    fn eeprom_stays_mountable_when_full() {
+        let mut backend = RamBackend::new();
+        {
+            let mut eeprom = Eeprom::init(&mut backend).unwrap();
+            for key in 1..5 {
+                eeprom.write(key, key * 10).unwrap();
+            }
+            assert_eq!(eeprom.write(5, 50), Err("The EEPROM is full."));
+        }
+        // the page the transfer targeted has been erased
+        assert!(backend.pages[1].iter().all(|&word| word == BLANK));
+
+        let eeprom = Eeprom::init(&mut backend).unwrap();
+        for key in 1..5 {
+            assert_eq!(eeprom.read(key), Some(key * 10));
+        }
+        assert_eq!(eeprom.read(5), None);
+    }
+
+    #[test]
+    fn eeprom_drops_a_transfer_that_cannot_complete() {
+        let mut backend = RamBackend::new();
+        {
+            let mut eeprom = Eeprom::init(&mut backend).unwrap();
+            for key in 1..5 {
+                eeprom.write(key, key * 10).unwrap();
+            }
+        }
+        // power lost after the new record of a fifth key reached the target
+        backend.cut_after(4);
+        {
+            let mut eeprom = Eeprom::init(&mut backend).unwrap();
+            assert!(eeprom.write(5, 50).is_err());
+        }
+        backend.power_on();
+        assert_eq!(backend.pages[1][0], PAGE_RECEIVE);
+
+        let eeprom = Eeprom::init(&mut backend).unwrap();
+        for key in 1..5 {
+            assert_eq!(eeprom.read(key), Some(key * 10));
+        }
+        assert_eq!(eeprom.read(5), None);
+        assert!(backend_is_blank(&eeprom, 1));
+    }
+
+    fn backend_is_blank(eeprom: &Eeprom<&mut RamBackend>, page: usize) -> bool {
+        (0..(PAGE_SIZE / 2)).all(|i| eeprom.backend.read(page, 2 * i) == BLANK)
+    }
+
+    #[test]
+    fn sector_backend_rejects_duplicate_sectors() {
+        let mut registers = [0u32; 6];
+        let flash = unsafe { &mut *(registers.as_mut_ptr() as *mut FlashRegisters) };
+        assert!(SectorBackend::new(flash, &[1, 2, 1], VoltageRange::V2_7To3_6).is_err());
+    }
+
+    #[test]
+    fn eeprom_recovers_from_power_cuts() {
+        let mut cut = 0;
+        loop {
+            let mut backend = RamBackend::new();
+            backend.cut_after(cut);
+            let (committed, interrupted) = replay(&mut backend);
+            if backend.powered {
+                // the whole sequence ran without reaching the cut
+                break;
+            }
+
+            backend.power_on();
+            let mut eeprom = Eeprom::init(&mut backend).unwrap();
+            for key in 1..4 {
+                let value = eeprom.read(key as u16);
+                match interrupted {
+                    Some((k, v)) if k as usize == key => {
+                        assert!((value == committed[key]) || (value == Some(v)), "cut {}", cut);
+                    },
+                    _ => assert_eq!(value, committed[key], "cut {}", cut)
+                }
+            }
+
+            // and it keeps working
+            for &(key, value) in WRITES.iter() {
+                eeprom.write(key, value).unwrap();
+                assert_eq!(eeprom.read(key), Some(value));
+            }
+            cut += 1;
+        }
+        // every program and erase of the sequence has been cut once
+        assert!(cut > WRITES.len() * 3);
+    }
+}
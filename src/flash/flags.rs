@@ -5,3 +5,46 @@ pub const ACR_ICEN: u32 = 0x00000200;
 pub const ACR_DCEN: u32 = 0x00000400;
 pub const ACR_ICRST: u32 = 0x00000800;
 pub const ACR_DCRST: u32 = 0x00001000;
+
+pub const KEY1: u32 = 0x45670123;
+pub const KEY2: u32 = 0xCDEF89AB;
+
+pub const SR_EOP: u32 = 0x00000001;
+pub const SR_OPERR: u32 = 0x00000002;
+pub const SR_WRPERR: u32 = 0x00000010;
+pub const SR_PGAERR: u32 = 0x00000020;
+pub const SR_PGPERR: u32 = 0x00000040;
+pub const SR_PGSERR: u32 = 0x00000080;
+pub const SR_BSY: u32 = 0x00010000;
+pub const SR_ERRORS: u32 = 0x000000F2;
+
+pub const CR_PG: u32 = 0x00000001;
+pub const CR_SER: u32 = 0x00000002;
+pub const CR_MER: u32 = 0x00000004;
+pub const CR_SNB_MASK: u32 = 0x00000078;
+pub const CR_SNB_SHIFT: u32 = 3;
+pub const CR_PSIZE_MASK: u32 = 0x00000300;
+pub const CR_PSIZE_SHIFT: u32 = 8;
+pub const CR_STRT: u32 = 0x00010000;
+pub const CR_EOPIE: u32 = 0x01000000;
+pub const CR_ERRIE: u32 = 0x02000000;
+pub const CR_LOCK: u32 = 0x80000000;
+
+pub const OPTKEY1: u32 = 0x08192A3B;
+pub const OPTKEY2: u32 = 0x4C5D6E7F;
+
+pub const OPTCR_OPTLOCK: u32 = 0x00000001;
+pub const OPTCR_OPTSTRT: u32 = 0x00000002;
+pub const OPTCR_BOR_LEV_MASK: u32 = 0x0000000C;
+pub const OPTCR_BOR_LEV_SHIFT: u32 = 2;
+pub const OPTCR_WDG_SW: u32 = 0x00000020;
+pub const OPTCR_NRST_STOP: u32 = 0x00000040;
+pub const OPTCR_NRST_STDBY: u32 = 0x00000080;
+pub const OPTCR_RDP_MASK: u32 = 0x0000FF00;
+pub const OPTCR_RDP_SHIFT: u32 = 8;
+pub const OPTCR_NWRP_MASK: u32 = 0x0FFF0000;
+pub const OPTCR_NWRP_SHIFT: u32 = 16;
+
+pub const RDP_LEVEL0: u32 = 0xAA;
+pub const RDP_LEVEL1: u32 = 0x55;
+pub const RDP_LEVEL2: u32 = 0xCC;
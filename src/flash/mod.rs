@@ -1,8 +1,22 @@
+use core::intrinsics;
+
 mod flags;
+/// EEPROM emulation
+pub mod eeprom;
 
 pub use self::flags::*;
 
 use registers::*;
+use time;
+
+pub const FLASH_BASE: usize = 0x08000000;
+pub const SECTOR_COUNT: u8 = 12;
+
+/// Worst case durations in milliseconds (x8 parallelism, 128K sector), with
+/// some margin.
+pub const PROGRAM_TIMEOUT: u32 = 10;
+pub const SECTOR_ERASE_TIMEOUT: u32 = 5_000;
+pub const MASS_ERASE_TIMEOUT: u32 = 40_000;
 
 #[repr(C)]
 pub struct FlashRegisters {
@@ -17,3 +31,459 @@ pub struct FlashRegisters {
 extern {
     pub fn flash_get() -> &mut FlashRegisters;
 }
+
+/// Supply voltage range, as used in the flash wait states table.
+#[derive(Copy, Clone, PartialEq)]
+pub enum VoltageRange {
+    V1_8To2_1,
+    V2_1To2_4,
+    V2_4To2_7,
+    V2_7To3_6
+}
+
+/// Flash access configuration for a given HCLK and supply voltage.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct WaitStates {
+    pub latency: u32,
+    pub prefetch: bool
+}
+
+/// Returns the minimum number of wait states needed to read the flash at
+/// `hclk` Hz under the `voltage` supply range (RM0033, "Read interface").
+///
+/// The prefetch buffer must stay disabled below 2.1V.
+pub fn wait_states(voltage: VoltageRange, hclk: u32) -> Result<WaitStates, &'static str> {
+    if 120_000_000 < hclk {
+        return Err("HCLK must not exceed 120MHz.");
+    }
+
+    // each wait state allows this many more Hz
+    let step = match voltage {
+        VoltageRange::V1_8To2_1 => 16_000_000,
+        VoltageRange::V2_1To2_4 => 18_000_000,
+        VoltageRange::V2_4To2_7 => 24_000_000,
+        VoltageRange::V2_7To3_6 => 30_000_000
+    };
+    let latency = if hclk == 0 { 0 } else { (hclk - 1) / step };
+
+    Ok(WaitStates {
+        latency: latency,
+        prefetch: voltage != VoltageRange::V1_8To2_1
+    })
+}
+
+impl FlashRegisters {
+    pub fn latency(&self) -> u32 {
+        self.access_control.read() & ACR_LATENCY_MASK
+    }
+
+    /// Programs the wait states and enables the caches, then checks that the
+    /// new latency has been taken into account.
+    pub fn set_wait_states(&mut self, ws: WaitStates) -> Result<(), &'static str> {
+        let prefetch = if ws.prefetch { ACR_PRFTEN } else { 0 };
+        self.access_control.update(
+            ACR_ICEN | ACR_DCEN | prefetch | ws.latency,
+            ACR_ICEN | ACR_DCEN | ACR_PRFTEN | ACR_LATENCY_MASK
+        );
+
+        if self.latency() != ws.latency {
+            return Err("Failed to set the flash latency.");
+        }
+        Ok(())
+    }
+}
+
+/// Number of bits programmed or erased at once (CR.PSIZE).
+#[derive(Copy, Clone, PartialEq)]
+pub enum Parallelism {
+    X8 = 0,
+    X16 = 1,
+    X32 = 2,
+    /// Requires an external Vpp, only usable for erase operations.
+    X64 = 3
+}
+impl Parallelism {
+    /// Widest parallelism allowed without external Vpp for a supply range.
+    pub fn from_voltage(voltage: VoltageRange) -> Parallelism {
+        match voltage {
+            VoltageRange::V1_8To2_1 => Parallelism::X8,
+            VoltageRange::V2_1To2_4 => Parallelism::X16,
+            VoltageRange::V2_4To2_7 => Parallelism::X16,
+            VoltageRange::V2_7To3_6 => Parallelism::X32
+        }
+    }
+    /// Number of bytes written per program operation.
+    pub fn size(self) -> usize {
+        1 << (self as usize)
+    }
+}
+
+/// Returns the start address and size of a sector.
+/// Sectors 0-3 are 16K, sector 4 is 64K and sectors 5-11 are 128K.
+pub fn sector_range(sector: u8) -> Option<(usize, usize)> {
+    match sector {
+        0...3 => Some((FLASH_BASE + (sector as usize) * 0x4000, 0x4000)),
+        4 => Some((FLASH_BASE + 0x10000, 0x10000)),
+        5...11 => Some((FLASH_BASE + 0x20000 * ((sector as usize) - 4), 0x20000)),
+        _ => None
+    }
+}
+
+/// Returns the sector holding `address`.
+pub fn sector_at(address: usize) -> Option<u8> {
+    for sector in 0..SECTOR_COUNT {
+        if let Some((start, size)) = sector_range(sector) {
+            if (start <= address) && (address < start + size) {
+                return Some(sector);
+            }
+        }
+    }
+    None
+}
+
+impl FlashRegisters {
+    pub fn is_locked(&self) -> bool {
+        (self.control.read() & CR_LOCK) == CR_LOCK
+    }
+
+    /// Unlocks the control register with the KEY1/KEY2 sequence.
+    pub fn unlock(&mut self) -> Result<(), &'static str> {
+        if self.is_locked() {
+            self.key.write(KEY1);
+            self.key.write(KEY2);
+        }
+        if self.is_locked() {
+            return Err("Failed to unlock the flash.");
+        }
+        Ok(())
+    }
+
+    /// Locks the control register until the next unlock sequence.
+    pub fn lock(&mut self) {
+        self.control.update(CR_LOCK, CR_LOCK);
+    }
+
+    /// Waits up to `timeout_ms` for the end of the current operation and
+    /// reports its errors.
+    pub fn wait_ready(&mut self, timeout_ms: u32) -> Result<(), &'static str> {
+        let ready = {
+            let status = &self.status;
+            time::wait_for(timeout_ms, || (status.read() & SR_BSY) == 0)
+        };
+        if !ready {
+            return Err("Flash operation timeout.");
+        }
+
+        let sr = self.status.read();
+        // flags are cleared by writing 1
+        self.status.write(sr & (SR_ERRORS | SR_EOP));
+
+        if (sr & SR_WRPERR) == SR_WRPERR {
+            Err("Flash write protection error.")
+        } else if (sr & SR_PGAERR) == SR_PGAERR {
+            Err("Flash programming alignment error.")
+        } else if (sr & SR_PGPERR) == SR_PGPERR {
+            Err("Flash programming parallelism error.")
+        } else if (sr & SR_PGSERR) == SR_PGSERR {
+            Err("Flash programming sequence error.")
+        } else if (sr & SR_OPERR) == SR_OPERR {
+            Err("Flash operation error.")
+        } else {
+            Ok(())
+        }
+    }
+
+    fn start_operation(&mut self, command: u32, psize: Parallelism) -> Result<(), &'static str> {
+        if self.is_locked() {
+            return Err("The flash is locked.");
+        }
+        if let Err(msg) = self.wait_ready(MASS_ERASE_TIMEOUT) {
+            return Err(msg);
+        }
+        self.control.update(
+            command | ((psize as u32) << CR_PSIZE_SHIFT),
+            CR_PG | CR_SER | CR_MER | CR_SNB_MASK | CR_PSIZE_MASK
+        );
+        Ok(())
+    }
+
+    /// Erases a sector. The flash must be unlocked.
+    pub fn erase_sector(&mut self, sector: u8, psize: Parallelism) -> Result<(), &'static str> {
+        if SECTOR_COUNT <= sector {
+            return Err("Invalid flash sector.");
+        }
+        if let Err(msg) = self.start_operation(CR_SER | ((sector as u32) << CR_SNB_SHIFT), psize) {
+            return Err(msg);
+        }
+        self.control.update(CR_STRT, CR_STRT);
+
+        let res = self.wait_ready(SECTOR_ERASE_TIMEOUT);
+        self.control.update(0, CR_SER | CR_SNB_MASK);
+        self.flush_caches();
+        res
+    }
+
+    /// Erases the whole flash. The flash must be unlocked.
+    pub fn mass_erase(&mut self, psize: Parallelism) -> Result<(), &'static str> {
+        if let Err(msg) = self.start_operation(CR_MER, psize) {
+            return Err(msg);
+        }
+        self.control.update(CR_STRT, CR_STRT);
+
+        let res = self.wait_ready(MASS_ERASE_TIMEOUT);
+        self.control.update(0, CR_MER);
+        self.flush_caches();
+        res
+    }
+
+    pub fn program_byte(&mut self, address: usize, value: u8) -> Result<(), &'static str> {
+        if let Err(msg) = self.start_operation(CR_PG, Parallelism::X8) {
+            return Err(msg);
+        }
+        unsafe { intrinsics::volatile_store(address as *mut u8, value); }
+
+        let res = self.wait_ready(PROGRAM_TIMEOUT);
+        self.control.update(0, CR_PG);
+        res
+    }
+
+    pub fn program_half_word(&mut self, address: usize, value: u16) -> Result<(), &'static str> {
+        if (address & 1) != 0 {
+            return Err("Flash programming alignment error.");
+        }
+        if let Err(msg) = self.start_operation(CR_PG, Parallelism::X16) {
+            return Err(msg);
+        }
+        unsafe { intrinsics::volatile_store(address as *mut u16, value); }
+
+        let res = self.wait_ready(PROGRAM_TIMEOUT);
+        self.control.update(0, CR_PG);
+        res
+    }
+
+    pub fn program_word(&mut self, address: usize, value: u32) -> Result<(), &'static str> {
+        if (address & 3) != 0 {
+            return Err("Flash programming alignment error.");
+        }
+        if let Err(msg) = self.start_operation(CR_PG, Parallelism::X32) {
+            return Err(msg);
+        }
+        unsafe { intrinsics::volatile_store(address as *mut u32, value); }
+
+        let res = self.wait_ready(PROGRAM_TIMEOUT);
+        self.control.update(0, CR_PG);
+        res
+    }
+
+    /// Programs `data` at `address` using `psize` wide writes. Both the address
+    /// and the length must be aligned on the parallelism. The caches are
+    /// flushed once done, single writes with `program_*` do not.
+    pub fn program(&mut self, address: usize, data: &[u8], psize: Parallelism) -> Result<(), &'static str> {
+        let size = psize.size();
+        if psize == Parallelism::X64 {
+            return Err("x64 programming is not supported.");
+        }
+        if ((address % size) != 0) || ((data.len() % size) != 0) {
+            return Err("Flash programming alignment error.");
+        }
+
+        for (i, chunk) in data.chunks(size).enumerate() {
+            let offset = address + i * size;
+            let res = match psize {
+                Parallelism::X8 => self.program_byte(offset, chunk[0]),
+                Parallelism::X16 => self.program_half_word(
+                    offset,
+                    (chunk[0] as u16) | ((chunk[1] as u16) << 8)
+                ),
+                _ => self.program_word(
+                    offset,
+                    (chunk[0] as u32) | ((chunk[1] as u32) << 8) |
+                    ((chunk[2] as u32) << 16) | ((chunk[3] as u32) << 24)
+                )
+            };
+            if let Err(msg) = res {
+                self.flush_caches();
+                return Err(msg);
+            }
+        }
+        self.flush_caches();
+        Ok(())
+    }
+
+    /// Drops the data and instruction cache lines, which may hold the
+    /// previous content of erased or programmed flash. The caches must be
+    /// disabled while being reset.
+    pub fn flush_caches(&mut self) {
+        let acr = self.access_control.read();
+        let enabled = acr & (ACR_DCEN | ACR_ICEN);
+        self.access_control.update(0, ACR_DCEN | ACR_ICEN);
+        self.access_control.update(ACR_DCRST | ACR_ICRST, ACR_DCRST | ACR_ICRST);
+        self.access_control.update(0, ACR_DCRST | ACR_ICRST);
+        self.access_control.update(enabled, ACR_DCEN | ACR_ICEN);
+    }
+}
+
+/// Confirmation that read-out protection level 2 may be set. Level 2
+/// permanently disables debug and option bytes changes.
+#[derive(PartialEq, Debug)]
+pub struct Irreversible {
+    _private: ()
+}
+impl Irreversible {
+    /// The caller accepts that the chip can never be debugged nor
+    /// reprogrammed through its option bytes again.
+    pub unsafe fn new() -> Irreversible {
+        Irreversible {
+            _private: ()
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ReadProtection {
+    Level0,
+    /// Going back to level 0 mass erases the flash.
+    Level1,
+    /// Only set through `program_options_level2`.
+    Level2
+}
+
+/// Brown-out reset threshold.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum BrownOutLevel {
+    Level3 = 0,
+    Level2 = 1,
+    Level1 = 2,
+    Off = 3
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct UserOptions {
+    /// WDG_SW: the independent watchdog is started by software.
+    pub watchdog_software: bool,
+    /// nRST_STOP: no reset is generated when entering stop mode.
+    pub no_reset_on_stop: bool,
+    /// nRST_STDBY: no reset is generated when entering standby mode.
+    pub no_reset_on_standby: bool
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct OptionBytes {
+    pub read_protection: ReadProtection,
+    /// One bit per sector, set when the sector is write protected.
+    pub write_protection: u16,
+    pub brown_out: BrownOutLevel,
+    pub user: UserOptions
+}
+
+impl FlashRegisters {
+    pub fn options_locked(&self) -> bool {
+        (self.option_control.read() & OPTCR_OPTLOCK) == OPTCR_OPTLOCK
+    }
+
+    /// Unlocks the option control register with the OPTKEY1/OPTKEY2 sequence.
+    pub fn unlock_options(&mut self) -> Result<(), &'static str> {
+        if self.options_locked() {
+            self.option_key.write(OPTKEY1);
+            self.option_key.write(OPTKEY2);
+        }
+        if self.options_locked() {
+            return Err("Failed to unlock the option bytes.");
+        }
+        Ok(())
+    }
+
+    pub fn lock_options(&mut self) {
+        self.option_control.update(OPTCR_OPTLOCK, OPTCR_OPTLOCK);
+    }
+
+    /// Reads the option bytes currently in effect.
+    pub fn option_bytes(&self) -> OptionBytes {
+        let optcr = self.option_control.read();
+
+        let read_protection = match (optcr & OPTCR_RDP_MASK) >> OPTCR_RDP_SHIFT {
+            RDP_LEVEL0 => ReadProtection::Level0,
+            RDP_LEVEL2 => ReadProtection::Level2,
+            _ => ReadProtection::Level1
+        };
+        let brown_out = match (optcr & OPTCR_BOR_LEV_MASK) >> OPTCR_BOR_LEV_SHIFT {
+            0 => BrownOutLevel::Level3,
+            1 => BrownOutLevel::Level2,
+            2 => BrownOutLevel::Level1,
+            _ => BrownOutLevel::Off
+        };
+        let nwrp = (optcr & OPTCR_NWRP_MASK) >> OPTCR_NWRP_SHIFT;
+
+        OptionBytes {
+            read_protection: read_protection,
+            write_protection: (!nwrp & 0xFFF) as u16,
+            brown_out: brown_out,
+            user: UserOptions {
+                watchdog_software: (optcr & OPTCR_WDG_SW) == OPTCR_WDG_SW,
+                no_reset_on_stop: (optcr & OPTCR_NRST_STOP) == OPTCR_NRST_STOP,
+                no_reset_on_standby: (optcr & OPTCR_NRST_STDBY) == OPTCR_NRST_STDBY
+            }
+        }
+    }
+
+    /// Programs the option bytes. The options must be unlocked.
+    ///
+    /// The new values are only loaded after a reset (or a power cycle when
+    /// the read protection changes). Read protection level 2 is refused, see
+    /// `program_options_level2`.
+    pub fn program_options(&mut self, options: &OptionBytes) -> Result<(), &'static str> {
+        if options.read_protection == ReadProtection::Level2 {
+            return Err("Read protection level 2 must be confirmed.");
+        }
+        self.write_options(options)
+    }
+
+    /// Programs the option bytes with read protection level 2.
+    pub fn program_options_level2(&mut self, options: &OptionBytes, _confirm: Irreversible) -> Result<(), &'static str> {
+        if options.read_protection != ReadProtection::Level2 {
+            return Err("These options do not set read protection level 2.");
+        }
+        self.write_options(options)
+    }
+
+    fn write_options(&mut self, options: &OptionBytes) -> Result<(), &'static str> {
+        if self.options_locked() {
+            return Err("The option bytes are locked.");
+        }
+        if self.option_bytes().read_protection == ReadProtection::Level2 {
+            return Err("The option bytes cannot be changed in read protection level 2.");
+        }
+        if (options.write_protection & !0xFFF) != 0 {
+            return Err("Invalid flash sector.");
+        }
+
+        let rdp = match options.read_protection {
+            ReadProtection::Level0 => RDP_LEVEL0,
+            ReadProtection::Level1 => RDP_LEVEL1,
+            ReadProtection::Level2 => RDP_LEVEL2
+        };
+        let mut optcr = (rdp << OPTCR_RDP_SHIFT) |
+                        ((!(options.write_protection as u32) & 0xFFF) << OPTCR_NWRP_SHIFT) |
+                        ((options.brown_out as u32) << OPTCR_BOR_LEV_SHIFT);
+        if options.user.watchdog_software {
+            optcr |= OPTCR_WDG_SW;
+        }
+        if options.user.no_reset_on_stop {
+            optcr |= OPTCR_NRST_STOP;
+        }
+        if options.user.no_reset_on_standby {
+            optcr |= OPTCR_NRST_STDBY;
+        }
+
+        if let Err(msg) = self.wait_ready(MASS_ERASE_TIMEOUT) {
+            return Err(msg);
+        }
+        self.option_control.update(
+            optcr,
+            OPTCR_RDP_MASK | OPTCR_NWRP_MASK | OPTCR_BOR_LEV_MASK |
+            OPTCR_WDG_SW | OPTCR_NRST_STOP | OPTCR_NRST_STDBY
+        );
+        self.option_control.update(OPTCR_OPTSTRT, OPTCR_OPTSTRT);
+        self.wait_ready(MASS_ERASE_TIMEOUT)
+    }
+}
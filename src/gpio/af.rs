@@ -0,0 +1,676 @@
+//! Alternate-function matrix of the STM32F2 family.
+
+use collections::string::String;
+use collections::string::ToString;
+
+use super::{AlternateFunction, Mode, PinPeripheral};
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Port {
+    A = 0,
+    B = 1,
+    C = 2,
+    D = 3,
+    E = 4,
+    F = 5,
+    G = 6,
+    H = 7,
+    I = 8
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Package {
+    LQFP64,
+    LQFP100,
+    LQFP144,
+    /// LQFP176 and UFBGA176
+    LQFP176
+}
+impl Package {
+    /// Whether `pin` of `port` is bonded out on this package.
+    pub fn has_pin(self, port: Port, pin: u8) -> bool {
+        if 15 < pin {
+            return false;
+        }
+        match (self, port) {
+            (_, Port::H) if pin < 2 => true,
+            (Package::LQFP64, Port::A) | (Package::LQFP64, Port::B) | (Package::LQFP64, Port::C) => true,
+            (Package::LQFP64, Port::D) => pin == 2,
+            (Package::LQFP64, _) => false,
+            (Package::LQFP100, p) => (p as u8) <= (Port::E as u8),
+            (Package::LQFP144, p) => (p as u8) <= (Port::G as u8),
+            (Package::LQFP176, Port::I) => pin < 12,
+            (Package::LQFP176, _) => true
+        }
+    }
+}
+
+/// A pin able to carry a signal, and the alternate function selecting it.
+#[derive(Copy, Clone)]
+pub struct AfPin {
+    pub signal: Signal,
+    pub port: Port,
+    pub pin: u8,
+    pub af: AlternateFunction
+}
+
+/// I2S signals sharing their pin and alternate function with an SPI signal go
+/// by the SPI name (`SPI2_MOSI` is `I2S2_SD`), ETH signals by their MII/RMII
+/// pair, so that a pin never carries two signals on the same function.
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum Signal {
+    MCO1,
+    MCO2,
+    RTC_50HZ,
+    JTMS_SWDIO,
+    JTCK_SWCLK,
+    JTDI,
+    JTDO_SWO,
+    NJTRST,
+    TRACECLK,
+    TRACED0,
+    TRACED1,
+    TRACED2,
+    TRACED3,
+    TIM1_CH1,
+    TIM1_CH1N,
+    TIM1_CH2,
+    TIM1_CH2N,
+    TIM1_CH3,
+    TIM1_CH3N,
+    TIM1_CH4,
+    TIM1_ETR,
+    TIM1_BKIN,
+    TIM2_CH1_ETR,
+    TIM2_CH2,
+    TIM2_CH3,
+    TIM2_CH4,
+    TIM3_CH1,
+    TIM3_CH2,
+    TIM3_CH3,
+    TIM3_CH4,
+    TIM3_ETR,
+    TIM4_CH1,
+    TIM4_CH2,
+    TIM4_CH3,
+    TIM4_CH4,
+    TIM4_ETR,
+    TIM5_CH1,
+    TIM5_CH2,
+    TIM5_CH3,
+    TIM5_CH4,
+    TIM8_CH1,
+    TIM8_CH1N,
+    TIM8_CH2,
+    TIM8_CH2N,
+    TIM8_CH3,
+    TIM8_CH3N,
+    TIM8_CH4,
+    TIM8_ETR,
+    TIM8_BKIN,
+    TIM9_CH1,
+    TIM9_CH2,
+    TIM10_CH1,
+    TIM11_CH1,
+    I2C1_SCL,
+    I2C1_SDA,
+    I2C1_SMBA,
+    I2C2_SCL,
+    I2C2_SDA,
+    I2C2_SMBA,
+    I2C3_SCL,
+    I2C3_SDA,
+    I2C3_SMBA,
+    SPI1_NSS,
+    SPI1_SCK,
+    SPI1_MISO,
+    SPI1_MOSI,
+    SPI2_NSS,
+    SPI2_SCK,
+    SPI2_MISO,
+    SPI2_MOSI,
+    SPI3_NSS,
+    SPI3_SCK,
+    SPI3_MISO,
+    SPI3_MOSI,
+    USART1_TX,
+    USART1_RX,
+    USART1_CK,
+    USART1_CTS,
+    USART1_RTS,
+    USART2_TX,
+    USART2_RX,
+    USART2_CK,
+    USART2_CTS,
+    USART2_RTS,
+    USART3_TX,
+    USART3_RX,
+    USART3_CK,
+    USART3_CTS,
+    USART3_RTS,
+    UART4_TX,
+    UART4_RX,
+    UART5_TX,
+    UART5_RX,
+    USART6_TX,
+    USART6_RX,
+    USART6_CK,
+    USART6_CTS,
+    USART6_RTS,
+    CAN1_RX,
+    CAN1_TX,
+    CAN2_RX,
+    CAN2_TX,
+    TIM12_CH1,
+    TIM12_CH2,
+    TIM13_CH1,
+    TIM14_CH1,
+    I2S_CKIN,
+    I2S2_MCK,
+    I2S3EXT_SD,
+    I2S2EXT_SD,
+    I2S3_MCK,
+    OTG_FS_SOF,
+    OTG_FS_ID,
+    OTG_FS_DM,
+    OTG_FS_DP,
+    OTG_HS_ULPI_D0,
+    OTG_HS_ULPI_CK,
+    OTG_HS_ULPI_D1,
+    OTG_HS_ULPI_D2,
+    OTG_HS_ULPI_D3,
+    OTG_HS_ULPI_D4,
+    OTG_HS_ULPI_D5,
+    OTG_HS_ULPI_D6,
+    OTG_HS_ULPI_D7,
+    OTG_HS_ULPI_STP,
+    OTG_HS_ULPI_DIR,
+    OTG_HS_ULPI_NXT,
+    ETH_MII_CRS,
+    ETH_MII_RX_CLK_RMII_REF_CLK,
+    ETH_MDIO,
+    ETH_MII_COL,
+    ETH_MII_RX_DV_RMII_CRS_DV,
+    ETH_MII_RXD2,
+    ETH_MII_RXD3,
+    ETH_PPS_OUT,
+    ETH_MII_TXD3,
+    ETH_MII_RX_ER,
+    ETH_TX_EN,
+    ETH_TXD0,
+    ETH_TXD1,
+    ETH_MDC,
+    ETH_MII_TXD2,
+    ETH_MII_TX_CLK,
+    ETH_RXD0,
+    ETH_RXD1,
+    OTG_HS_SOF,
+    OTG_HS_ID,
+    OTG_HS_DM,
+    OTG_HS_DP,
+    SDIO_D0,
+    SDIO_D1,
+    SDIO_D2,
+    SDIO_D3,
+    SDIO_D4,
+    SDIO_D5,
+    SDIO_D6,
+    SDIO_D7,
+    SDIO_CK,
+    SDIO_CMD,
+    FSMC_NL,
+    FSMC_CLK,
+    FSMC_NOE,
+    FSMC_NWE,
+    FSMC_NWAIT,
+    FSMC_NE1_NCE2,
+    FSMC_NE2_NCE3,
+    FSMC_NE3_NCE4_1,
+    FSMC_NCE4_2,
+    FSMC_NE4,
+    FSMC_NBL0,
+    FSMC_NBL1,
+    FSMC_NIORD,
+    FSMC_NREG,
+    FSMC_NIOWR,
+    FSMC_CD,
+    FSMC_INTR,
+    FSMC_INT2,
+    FSMC_INT3,
+    FSMC_D0,
+    FSMC_D1,
+    FSMC_D2,
+    FSMC_D3,
+    FSMC_D4,
+    FSMC_D5,
+    FSMC_D6,
+    FSMC_D7,
+    FSMC_D8,
+    FSMC_D9,
+    FSMC_D10,
+    FSMC_D11,
+    FSMC_D12,
+    FSMC_D13,
+    FSMC_D14,
+    FSMC_D15,
+    FSMC_A0,
+    FSMC_A1,
+    FSMC_A2,
+    FSMC_A3,
+    FSMC_A4,
+    FSMC_A5,
+    FSMC_A6,
+    FSMC_A7,
+    FSMC_A8,
+    FSMC_A9,
+    FSMC_A10,
+    FSMC_A11,
+    FSMC_A12,
+    FSMC_A13,
+    FSMC_A14,
+    FSMC_A15,
+    FSMC_A16,
+    FSMC_A17,
+    FSMC_A18,
+    FSMC_A19,
+    FSMC_A20,
+    FSMC_A21,
+    FSMC_A22,
+    FSMC_A23,
+    FSMC_A24,
+    FSMC_A25,
+    DCMI_HSYNC,
+    DCMI_PIXCLK,
+    DCMI_VSYNC,
+    DCMI_D0,
+    DCMI_D1,
+    DCMI_D2,
+    DCMI_D3,
+    DCMI_D4,
+    DCMI_D5,
+    DCMI_D6,
+    DCMI_D7,
+    DCMI_D8,
+    DCMI_D9,
+    DCMI_D10,
+    DCMI_D11,
+    DCMI_D12,
+    DCMI_D13,
+    EVENTOUT
+}
+
+macro_rules! af_table {
+    ($($signal:ident: $($port:ident $pin:tt),*; $af:ident;)*) => {
+        [$($(
+            AfPin { signal: Signal::$signal, port: Port::$port, pin: $pin, af: AlternateFunction::$af },
+        )*)*]
+    }
+}
+
+/// Alternate functions AF0 to AF15 of every signal (datasheet table 9), as
+/// `SIGNAL: PORT pin, ...; AFx;` entries handed to `$table!`. Both `AF_TABLE`
+/// and the typed pins' `HasAlternate` impls are generated from it.
+macro_rules! af_data {
+    ($table:ident) => {
+        $table! {
+            MCO1: A 8; AF0;
+            MCO2: C 9; AF0;
+            RTC_50HZ: B 15; AF0;
+            JTMS_SWDIO: A 13; AF0;
+            JTCK_SWCLK: A 14; AF0;
+            JTDI: A 15; AF0;
+            JTDO_SWO: B 3; AF0;
+            NJTRST: B 4; AF0;
+            TRACECLK: E 2; AF0;
+            TRACED0: E 3; AF0;
+            TRACED1: E 4; AF0;
+            TRACED2: E 5; AF0;
+            TRACED3: E 6; AF0;
+            TIM1_CH1: A 8, E 9; AF1;
+            TIM1_CH1N: A 7, B 13, E 8; AF1;
+            TIM1_CH2: A 9, E 11; AF1;
+            TIM1_CH2N: B 0, B 14, E 10; AF1;
+            TIM1_CH3: A 10, E 13; AF1;
+            TIM1_CH3N: B 1, B 15, E 12; AF1;
+            TIM1_CH4: A 11, E 14; AF1;
+            TIM1_ETR: A 12, E 7; AF1;
+            TIM1_BKIN: A 6, B 12, E 15; AF1;
+            TIM2_CH1_ETR: A 0, A 5, A 15; AF1;
+            TIM2_CH2: A 1, B 3; AF1;
+            TIM2_CH3: A 2, B 10; AF1;
+            TIM2_CH4: A 3, B 11; AF1;
+            TIM3_CH1: A 6, B 4, C 6; AF2;
+            TIM3_CH2: A 7, B 5, C 7; AF2;
+            TIM3_CH3: B 0, C 8; AF2;
+            TIM3_CH4: B 1, C 9; AF2;
+            TIM3_ETR: D 2; AF2;
+            TIM4_CH1: B 6, D 12; AF2;
+            TIM4_CH2: B 7, D 13; AF2;
+            TIM4_CH3: B 8, D 14; AF2;
+            TIM4_CH4: B 9, D 15; AF2;
+            TIM4_ETR: E 0; AF2;
+            TIM5_CH1: A 0, H 10; AF2;
+            TIM5_CH2: A 1, H 11; AF2;
+            TIM5_CH3: A 2, H 12; AF2;
+            TIM5_CH4: A 3, I 0; AF2;
+            TIM8_CH1: C 6, I 5; AF3;
+            TIM8_CH1N: A 5, A 7, H 13; AF3;
+            TIM8_CH2: C 7, I 6; AF3;
+            TIM8_CH2N: B 0, B 14, H 14; AF3;
+            TIM8_CH3: C 8, I 7; AF3;
+            TIM8_CH3N: B 1, B 15, H 15; AF3;
+            TIM8_CH4: C 9, I 2; AF3;
+            TIM8_ETR: A 0, I 3; AF3;
+            TIM8_BKIN: A 6, I 4; AF3;
+            TIM9_CH1: A 2, E 5; AF3;
+            TIM9_CH2: A 3, E 6; AF3;
+            TIM10_CH1: B 8, F 6; AF3;
+            TIM11_CH1: B 9, F 7; AF3;
+            I2C1_SCL: B 6, B 8; AF4;
+            I2C1_SDA: B 7, B 9; AF4;
+            I2C1_SMBA: B 5; AF4;
+            I2C2_SCL: B 10, F 1, H 4; AF4;
+            I2C2_SDA: B 11, F 0, H 5; AF4;
+            I2C2_SMBA: B 12, F 2, H 6; AF4;
+            I2C3_SCL: A 8, H 7; AF4;
+            I2C3_SDA: C 9, H 8; AF4;
+            I2C3_SMBA: A 9, H 9; AF4;
+            SPI1_NSS: A 4, A 15; AF5;
+            SPI1_SCK: A 5, B 3; AF5;
+            SPI1_MISO: A 6, B 4; AF5;
+            SPI1_MOSI: A 7, B 5; AF5;
+            SPI2_NSS: B 9, B 12, I 0; AF5;
+            SPI2_SCK: B 10, B 13, I 1; AF5;
+            SPI2_MISO: B 14, C 2, I 2; AF5;
+            SPI2_MOSI: B 15, C 3, I 3; AF5;
+            SPI3_NSS: A 4, A 15; AF6;
+            SPI3_SCK: B 3, C 10; AF6;
+            SPI3_MISO: B 4, C 11; AF6;
+            SPI3_MOSI: B 5, C 12; AF6;
+            USART1_TX: A 9, B 6; AF7;
+            USART1_RX: A 10, B 7; AF7;
+            USART1_CK: A 8; AF7;
+            USART1_CTS: A 11; AF7;
+            USART1_RTS: A 12; AF7;
+            USART2_TX: A 2, D 5; AF7;
+            USART2_RX: A 3, D 6; AF7;
+            USART2_CK: A 4, D 7; AF7;
+            USART2_CTS: A 0, D 3; AF7;
+            USART2_RTS: A 1, D 4; AF7;
+            USART3_TX: B 10, C 10, D 8; AF7;
+            USART3_RX: B 11, C 11, D 9; AF7;
+            USART3_CK: B 12, C 12, D 10; AF7;
+            USART3_CTS: B 13, D 11; AF7;
+            USART3_RTS: B 14, D 12; AF7;
+            UART4_TX: A 0, C 10; AF8;
+            UART4_RX: A 1, C 11; AF8;
+            UART5_TX: C 12; AF8;
+            UART5_RX: D 2; AF8;
+            USART6_TX: C 6, G 14; AF8;
+            USART6_RX: C 7, G 9; AF8;
+            USART6_CK: C 8, G 7; AF8;
+            USART6_CTS: G 13, G 15; AF8;
+            USART6_RTS: G 8, G 12; AF8;
+            CAN1_RX: A 11, B 8, D 0, I 9; AF9;
+            CAN1_TX: A 12, B 9, D 1, H 13; AF9;
+            CAN2_RX: B 5, B 12; AF9;
+            CAN2_TX: B 6, B 13; AF9;
+            TIM12_CH1: B 14, H 6; AF9;
+            TIM12_CH2: B 15, H 9; AF9;
+            TIM13_CH1: A 6, F 8; AF9;
+            TIM14_CH1: A 7, F 9; AF9;
+            I2S_CKIN: C 9; AF5;
+            I2S2_MCK: C 6; AF5;
+            I2S3EXT_SD: C 11; AF5;
+            I2S2EXT_SD: B 14, C 2, I 2; AF6;
+            I2S3_MCK: C 7; AF6;
+            I2S3EXT_SD: B 4; AF7;
+            OTG_FS_SOF: A 8; AF10;
+            OTG_FS_ID: A 10; AF10;
+            OTG_FS_DM: A 11; AF10;
+            OTG_FS_DP: A 12; AF10;
+            OTG_HS_ULPI_D0: A 3; AF10;
+            OTG_HS_ULPI_CK: A 5; AF10;
+            OTG_HS_ULPI_D1: B 0; AF10;
+            OTG_HS_ULPI_D2: B 1; AF10;
+            OTG_HS_ULPI_D3: B 10; AF10;
+            OTG_HS_ULPI_D4: B 11; AF10;
+            OTG_HS_ULPI_D5: B 12; AF10;
+            OTG_HS_ULPI_D6: B 13; AF10;
+            OTG_HS_ULPI_D7: B 5; AF10;
+            OTG_HS_ULPI_STP: C 0; AF10;
+            OTG_HS_ULPI_DIR: C 2, I 11; AF10;
+            OTG_HS_ULPI_NXT: C 3, H 4; AF10;
+            ETH_MII_CRS: A 0, H 2; AF11;
+            ETH_MII_RX_CLK_RMII_REF_CLK: A 1; AF11;
+            ETH_MDIO: A 2; AF11;
+            ETH_MII_COL: A 3, H 3; AF11;
+            ETH_MII_RX_DV_RMII_CRS_DV: A 7; AF11;
+            ETH_MII_RXD2: B 0, H 6; AF11;
+            ETH_MII_RXD3: B 1, H 7; AF11;
+            ETH_PPS_OUT: B 5, G 8; AF11;
+            ETH_MII_TXD3: B 8, E 2; AF11;
+            ETH_MII_RX_ER: B 10, I 10; AF11;
+            ETH_TX_EN: B 11, G 11; AF11;
+            ETH_TXD0: B 12, G 13; AF11;
+            ETH_TXD1: B 13, G 14; AF11;
+            ETH_MDC: C 1; AF11;
+            ETH_MII_TXD2: C 2; AF11;
+            ETH_MII_TX_CLK: C 3; AF11;
+            ETH_RXD0: C 4; AF11;
+            ETH_RXD1: C 5; AF11;
+            OTG_HS_SOF: A 4; AF12;
+            OTG_HS_ID: B 12; AF12;
+            OTG_HS_DM: B 14; AF12;
+            OTG_HS_DP: B 15; AF12;
+            SDIO_D0: C 8; AF12;
+            SDIO_D1: C 9; AF12;
+            SDIO_D2: C 10; AF12;
+            SDIO_D3: C 11; AF12;
+            SDIO_D4: B 8; AF12;
+            SDIO_D5: B 9; AF12;
+            SDIO_D6: C 6; AF12;
+            SDIO_D7: C 7; AF12;
+            SDIO_CK: C 12; AF12;
+            SDIO_CMD: D 2; AF12;
+            FSMC_NL: B 7; AF12;
+            FSMC_CLK: D 3; AF12;
+            FSMC_NOE: D 4; AF12;
+            FSMC_NWE: D 5; AF12;
+            FSMC_NWAIT: D 6; AF12;
+            FSMC_NE1_NCE2: D 7; AF12;
+            FSMC_NE2_NCE3: G 9; AF12;
+            FSMC_NE3_NCE4_1: G 10; AF12;
+            FSMC_NCE4_2: G 11; AF12;
+            FSMC_NE4: G 12; AF12;
+            FSMC_NBL0: E 0; AF12;
+            FSMC_NBL1: E 1; AF12;
+            FSMC_NIORD: F 6; AF12;
+            FSMC_NREG: F 7; AF12;
+            FSMC_NIOWR: F 8; AF12;
+            FSMC_CD: F 9; AF12;
+            FSMC_INTR: F 10; AF12;
+            FSMC_INT2: G 6; AF12;
+            FSMC_INT3: G 7; AF12;
+            FSMC_D0: D 14; AF12;
+            FSMC_D1: D 15; AF12;
+            FSMC_D2: D 0; AF12;
+            FSMC_D3: D 1; AF12;
+            FSMC_D4: E 7; AF12;
+            FSMC_D5: E 8; AF12;
+            FSMC_D6: E 9; AF12;
+            FSMC_D7: E 10; AF12;
+            FSMC_D8: E 11; AF12;
+            FSMC_D9: E 12; AF12;
+            FSMC_D10: E 13; AF12;
+            FSMC_D11: E 14; AF12;
+            FSMC_D12: E 15; AF12;
+            FSMC_D13: D 8; AF12;
+            FSMC_D14: D 9; AF12;
+            FSMC_D15: D 10; AF12;
+            FSMC_A0: F 0; AF12;
+            FSMC_A1: F 1; AF12;
+            FSMC_A2: F 2; AF12;
+            FSMC_A3: F 3; AF12;
+            FSMC_A4: F 4; AF12;
+            FSMC_A5: F 5; AF12;
+            FSMC_A6: F 12; AF12;
+            FSMC_A7: F 13; AF12;
+            FSMC_A8: F 14; AF12;
+            FSMC_A9: F 15; AF12;
+            FSMC_A10: G 0; AF12;
+            FSMC_A11: G 1; AF12;
+            FSMC_A12: G 2; AF12;
+            FSMC_A13: G 3; AF12;
+            FSMC_A14: G 4; AF12;
+            FSMC_A15: G 5; AF12;
+            FSMC_A16: D 11; AF12;
+            FSMC_A17: D 12; AF12;
+            FSMC_A18: D 13; AF12;
+            FSMC_A19: E 3; AF12;
+            FSMC_A20: E 4; AF12;
+            FSMC_A21: E 5; AF12;
+            FSMC_A22: E 6; AF12;
+            FSMC_A23: E 2; AF12;
+            FSMC_A24: G 13; AF12;
+            FSMC_A25: G 14; AF12;
+            DCMI_HSYNC: A 4, H 8; AF13;
+            DCMI_PIXCLK: A 6; AF13;
+            DCMI_VSYNC: B 7, I 5; AF13;
+            DCMI_D0: A 9, C 6, H 9; AF13;
+            DCMI_D1: A 10, C 7, H 10; AF13;
+            DCMI_D2: C 8, E 0, G 10, H 11; AF13;
+            DCMI_D3: C 9, E 1, G 11, H 12; AF13;
+            DCMI_D4: C 11, E 4, H 14; AF13;
+            DCMI_D5: B 6, D 3, I 4; AF13;
+            DCMI_D6: B 8, E 5, I 6; AF13;
+            DCMI_D7: B 9, E 6, I 7; AF13;
+            DCMI_D8: C 10, H 6, I 1; AF13;
+            DCMI_D9: C 12, H 7, I 2; AF13;
+            DCMI_D10: B 5, I 3; AF13;
+            DCMI_D11: D 2, H 15; AF13;
+            DCMI_D12: F 11; AF13;
+            DCMI_D13: G 15, I 0; AF13;
+            EVENTOUT:
+                A 0, A 1, A 2, A 3, A 4, A 5, A 6, A 7, A 8, A 9, A 10, A 11, A 12, A 13, A 14, A 15,
+                B 0, B 1, B 2, B 3, B 4, B 5, B 6, B 7, B 8, B 9, B 10, B 11, B 12, B 13, B 14, B 15,
+                C 0, C 1, C 2, C 3, C 4, C 5, C 6, C 7, C 8, C 9, C 10, C 11, C 12, C 13, C 14, C 15,
+                D 0, D 1, D 2, D 3, D 4, D 5, D 6, D 7, D 8, D 9, D 10, D 11, D 12, D 13, D 14, D 15,
+                E 0, E 1, E 2, E 3, E 4, E 5, E 6, E 7, E 8, E 9, E 10, E 11, E 12, E 13, E 14, E 15,
+                F 0, F 1, F 2, F 3, F 4, F 5, F 6, F 7, F 8, F 9, F 10, F 11, F 12, F 13, F 14, F 15,
+                G 0, G 1, G 2, G 3, G 4, G 5, G 6, G 7, G 8, G 9, G 10, G 11, G 12, G 13, G 14, G 15,
+                H 0, H 1, H 2, H 3, H 4, H 5, H 6, H 7, H 8, H 9, H 10, H 11, H 12, H 13, H 14, H 15,
+                I 0, I 1, I 2, I 3, I 4, I 5, I 6, I 7, I 8, I 9, I 10, I 11;
+                AF15;
+        }
+    }
+}
+
+pub static AF_TABLE: &'static [AfPin] = &af_data!(af_table);
+
+/// Iterator over the pins of a package able to carry a signal.
+pub struct SignalPins {
+    signal: Signal,
+    package: Package,
+    index: usize
+}
+impl Iterator for SignalPins {
+    type Item = &'static AfPin;
+
+    fn next(&mut self) -> Option<&'static AfPin> {
+        while self.index < AF_TABLE.len() {
+            let entry = &AF_TABLE[self.index];
+            self.index += 1;
+            if (entry.signal == self.signal) && self.package.has_pin(entry.port, entry.pin) {
+                return Some(entry);
+            }
+        }
+        None
+    }
+}
+
+/// Lists the pins of `package` that can carry `signal`.
+pub fn pins_for(signal: Signal, package: Package) -> SignalPins {
+    SignalPins {
+        signal: signal,
+        package: package,
+        index: 0
+    }
+}
+
+/// Returns the alternate function connecting `signal` to `pin` of `port`.
+pub fn find(signal: Signal, port: Port, pin: u8) -> Option<AlternateFunction> {
+    for entry in AF_TABLE.iter() {
+        if (entry.signal == signal) && (entry.port == port) && (entry.pin == pin) {
+            return Some(entry.af);
+        }
+    }
+    None
+}
+
+/// Checks that `pin` is set to the alternate function carrying `signal`.
+pub fn check_pin(pin: &PinPeripheral, signal: Signal) -> Result<(), String> {
+    let af = match pin.mode {
+        Mode::AlternateFunction(af) => af,
+        _ => return Err("The pin is not in alternate function mode".to_string())
+    };
+    let port = match pin.port.index() {
+        Some(0) => Port::A,
+        Some(1) => Port::B,
+        Some(2) => Port::C,
+        Some(3) => Port::D,
+        Some(4) => Port::E,
+        Some(5) => Port::F,
+        Some(6) => Port::G,
+        Some(7) => Port::H,
+        Some(8) => Port::I,
+        _ => return Err("Invalid GPIO port clock".to_string())
+    };
+    if 15 < pin.pin {
+        return Err("Invalid pin number".to_string())
+    }
+    match find(signal, port, pin.pin as u8) {
+        Some(expected) if (expected as u8) == (af as u8) => Ok(()),
+        Some(_) => Err("Wrong alternate function for this pin".to_string()),
+        None => Err("This pin cannot carry this signal".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn af(signal: Signal, port: Port, pin: u8) -> Option<u8> {
+        find(signal, port, pin).map(|af| af as u8)
+    }
+
+    #[test]
+    fn af_table_has_one_signal_per_function() {
+        for (i, a) in AF_TABLE.iter().enumerate() {
+            for b in AF_TABLE[i + 1..].iter() {
+                assert!(!((a.port == b.port) && (a.pin == b.pin) && ((a.af as u8) == (b.af as u8))));
+            }
+        }
+    }
+
+    #[test]
+    fn af_table_covers_every_function() {
+        assert_eq!(af(Signal::MCO1, Port::A, 8), Some(0));
+        assert_eq!(af(Signal::SPI2_MOSI, Port::C, 3), Some(5));
+        assert_eq!(af(Signal::I2S3_MCK, Port::C, 7), Some(6));
+        assert_eq!(af(Signal::OTG_FS_DP, Port::A, 12), Some(10));
+        assert_eq!(af(Signal::ETH_MDIO, Port::A, 2), Some(11));
+        assert_eq!(af(Signal::FSMC_D0, Port::D, 14), Some(12));
+        assert_eq!(af(Signal::SDIO_CK, Port::C, 12), Some(12));
+        assert_eq!(af(Signal::DCMI_PIXCLK, Port::A, 6), Some(13));
+        assert_eq!(af(Signal::EVENTOUT, Port::I, 11), Some(15));
+        assert_eq!(af(Signal::EVENTOUT, Port::I, 12), None);
+    }
+
+    #[test]
+    fn pins_for_filters_by_package() {
+        assert_eq!(pins_for(Signal::DCMI_D2, Package::LQFP176).count(), 4);
+        assert_eq!(pins_for(Signal::DCMI_D2, Package::LQFP64).count(), 1);
+    }
+}
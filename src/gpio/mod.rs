@@ -0,0 +1,649 @@
+use collections::string::String;
+use collections::string::ToString;
+
+use silica::peripheral::gpio::{Input as IInput, Output as IOutput};
+
+use rcc;
+use Peripheral;
+use registers::*;
+
+/// Alternate-function mapping
+#[macro_use]
+pub mod af;
+/// Typestate pins
+pub mod typed;
+
+#[derive(Copy, Clone)]
+pub enum AlternateFunction {
+    AF0 = 0,
+    AF1 = 1,
+    AF2 = 2,
+    AF3 = 3,
+    AF4 = 4,
+    AF5 = 5,
+    AF6 = 6,
+    AF7 = 7,
+    AF8 = 8,
+    AF9 = 9,
+    AF10 = 10,
+    AF11 = 11,
+    AF12 = 12,
+    AF13 = 13,
+    AF14 = 14,
+    AF15 = 15,
+}
+
+#[derive(Copy, Clone)]
+pub enum OutputType {
+    PushPull    = 0,
+    OpenDrain   = 1
+}
+
+pub enum Mode {
+    In,
+    Out(OutputType, bool),
+    AlternateFunction(AlternateFunction),
+    Analog
+}
+
+#[derive(Copy, Clone)]
+pub enum Frequency {
+    F2MHz   = 0,
+    F20MHz  = 1,
+    F50MHz  = 2,
+    F100MHz = 3
+}
+
+#[derive(Copy, Clone)]
+pub enum PullSide {
+    None    = 0,
+    Up      = 1,
+    Down    = 2,
+    Both    = 3
+}
+
+#[repr(C)]
+pub struct PortRegisters {
+    mode: Rw<u32>,
+    output_type: Rw<u16>,
+    reserved0: u16,
+    output_speed: Rw<u32>,
+    pu_pd: Rw<u32>,
+    input_data: Ro<u16>,
+    reserved1: u16,
+    output_data: Rw<u16>,
+    reserved2: u16,
+    bit_set_reset: Wo<u32>,
+    lock: Rw<u32>,
+    alternate_function_low: Rw<u32>,
+    alternate_function_high: Rw<u32>
+}
+
+const LCKR_LCKK: u32 = 0x00010000;
+
+impl PortRegisters {
+    /// Returns the pins whose configuration is frozen until the next reset.
+    pub fn locked_pins(&self) -> u16 {
+        self.lock.read() as u16
+    }
+
+    /// Freezes the configuration of `pins` (a bit mask) until the next reset.
+    /// Pins locked previously stay locked. Once a port has been locked its
+    /// LCKR is frozen too, so locking more pins later fails.
+    pub fn lock_pins(&mut self, pins: u16) -> Result<(), String> {
+        let value = (pins | self.locked_pins()) as u32;
+        let lckr = &mut self.lock;
+        ::critical_section(|| {
+            // the key sequence must not be interrupted
+            lckr.write(LCKR_LCKK | value);
+            lckr.write(value);
+            lckr.write(LCKR_LCKK | value);
+            lckr.read();
+        });
+        if (lckr.read() & LCKR_LCKK) != LCKR_LCKK {
+            return Err("Failed to lock the port configuration".to_string())
+        }
+        if ((lckr.read() as u16) & pins) != pins {
+            return Err("The port configuration is already locked".to_string())
+        }
+        Ok(())
+    }
+}
+
+const GPIOA_BASE: usize = 0x40020000;
+const PORT_SIZE: usize = 0x400;
+const PORT_COUNT: usize = 9;
+
+// pins initialized on each port, GPIOA to GPIOI
+static mut PINS_IN_USE: [u16; PORT_COUNT] = [0; PORT_COUNT];
+
+/// Returns whether the pin's alternate function is in AFRH, and its shift in
+/// that register.
+pub fn af_position(pin: u32) -> (bool, u32) {
+    if pin < 8 {
+        (false, pin * 4)
+    } else {
+        (true, (pin - 8) * 4)
+    }
+}
+
+/// Programs every configuration field of `pin`.
+fn configure_pin(port: &mut PortRegisters, pin: u32, mode: u32, otype: u16, speed: Frequency,
+                 pull_side: PullSide, state: bool, af: u32) {
+    let onebit_mask = 1 << pin;
+    let twobit_shift = pin * 2;
+    let twobit_mask = 3 << twobit_shift;
+    let (af_high, af_shift) = af_position(pin);
+    let af_mask = 0xF << af_shift;
+
+    port.mode.update(mode << twobit_shift, twobit_mask);
+    port.output_type.update(otype << pin, onebit_mask);
+    port.output_speed.update((speed as u32) << twobit_shift, twobit_mask);
+    port.pu_pd.update((pull_side as u32) << twobit_shift, twobit_mask);
+    if state {
+        port.output_data.update(1 << pin, onebit_mask);
+    } else {
+        port.output_data.update(0, onebit_mask);
+    }
+    if af_high {
+        port.alternate_function_high.update(af << af_shift, af_mask);
+    } else {
+        port.alternate_function_low.update(af << af_shift, af_mask);
+    }
+}
+
+/// Records `pin` of port `index` as used or released, returns the pins of
+/// that port still in use.
+fn set_pin_in_use(index: usize, pin: u32, in_use: bool) -> u16 {
+    ::critical_section(|| unsafe {
+        if in_use {
+            PINS_IN_USE[index] |= 1 << pin;
+        } else {
+            PINS_IN_USE[index] &= !(1 << pin);
+        }
+        PINS_IN_USE[index]
+    })
+}
+
+/// Marks `pin` of port `index` as used, fails if it already was.
+fn claim_pin(index: usize, pin: u32) -> bool {
+    ::critical_section(|| unsafe {
+        if (PINS_IN_USE[index] & (1 << pin)) != 0 {
+            return false;
+        }
+        PINS_IN_USE[index] |= 1 << pin;
+        true
+    })
+}
+
+pub struct PortPeripheral {
+    pub base_address: *mut PortRegisters,
+    pub clock: rcc::RCCPeripheral
+}
+impl PortPeripheral {
+    /// 0 for GPIOA up to 8 for GPIOI, from the port clock.
+    pub fn index(&self) -> Option<usize> {
+        match self.clock.clock {
+            rcc::Clock::GPIOA => Some(0),
+            rcc::Clock::GPIOB => Some(1),
+            rcc::Clock::GPIOC => Some(2),
+            rcc::Clock::GPIOD => Some(3),
+            rcc::Clock::GPIOE => Some(4),
+            rcc::Clock::GPIOF => Some(5),
+            rcc::Clock::GPIOG => Some(6),
+            rcc::Clock::GPIOH => Some(7),
+            rcc::Clock::GPIOI => Some(8),
+            _ => None
+        }
+    }
+
+    /// Pins of this port that are currently initialized, as a bit mask.
+    pub fn pins_in_use(&self) -> u16 {
+        match self.index() {
+            Some(index) => ::critical_section(|| unsafe { PINS_IN_USE[index] }),
+            None => 0
+        }
+    }
+
+    /// Records `pin` as used or released, returns the remaining pins in use.
+    fn set_pin_in_use(&self, pin: u32, in_use: bool) -> u16 {
+        match self.index() {
+            Some(index) => set_pin_in_use(index, pin, in_use),
+            None => 0
+        }
+    }
+
+    /// Reads the input level of all the pins at once.
+    pub fn read(&self) -> u16 {
+        unsafe { (*self.base_address).input_data.read() }
+    }
+
+    /// Returns the output command of all the pins.
+    pub fn output(&self) -> u16 {
+        unsafe { (*self.base_address).output_data.read() }
+    }
+
+    /// Sets the `set` pins and clears the `reset` pins with a single BSRR
+    /// write, so they all change on the same clock cycle. Set wins when a pin
+    /// is in both masks.
+    pub fn set_reset(&self, set: u16, reset: u16) {
+        unsafe {
+            (*self.base_address).bit_set_reset.write(((reset as u32) << 16) | (set as u32));
+        }
+    }
+
+    /// Drives the `mask` pins to the matching bits of `value` at once, the
+    /// other pins are left untouched.
+    pub fn write_masked(&self, mask: u16, value: u16) {
+        self.set_reset(value & mask, !value & mask);
+    }
+
+    /// Returns the pins whose configuration is frozen until the next reset.
+    pub fn locked_pins(&self) -> u16 {
+        unsafe { (*self.base_address).locked_pins() }
+    }
+
+    /// Freezes the configuration of `pins` (a bit mask) until the next reset,
+    /// see `PortRegisters::lock_pins`.
+    pub fn lock(&self, pins: u16) -> Result<(), String> {
+        unsafe { (*self.base_address).lock_pins(pins) }
+    }
+}
+impl Peripheral for PortPeripheral {
+    fn init(&self) -> Result<(), String> {
+        if self.index().is_none() {
+            return Err("Invalid GPIO port clock".to_string())
+        }
+        init_peripheral![Some(&self.clock)];
+
+        Ok(())
+    }
+    /// Gates the port clock. Fails while some of its pins are initialized.
+    fn deinit(&self) -> Result<(), String> {
+        if self.pins_in_use() != 0 {
+            return Err("Some pins of this port are still in use".to_string())
+        }
+        self.clock.deinit()
+    }
+}
+
+/// Contiguous pins of a port used as a parallel bus, bit 0 of a value being
+/// on `first_pin`. The pins are configured by their `PinPeripheral`.
+pub struct ParallelBus<'a> {
+    port: &'a PortPeripheral,
+    first_pin: u32,
+    width: u32
+}
+impl<'a> ParallelBus<'a> {
+    pub fn new(port: &'a PortPeripheral, first_pin: u32, width: u32) -> Result<ParallelBus<'a>, String> {
+        if (width == 0) || (16 < first_pin + width) {
+            return Err("The bus does not fit in the port".to_string())
+        }
+        Ok(ParallelBus {
+            port: port,
+            first_pin: first_pin,
+            width: width
+        })
+    }
+
+    /// Port pins of this bus.
+    pub fn mask(&self) -> u16 {
+        (((1u32 << self.width) - 1) << self.first_pin) as u16
+    }
+
+    /// Drives all the bus lines at once.
+    pub fn write(&self, value: u16) {
+        self.port.write_masked(self.mask(), value << self.first_pin);
+    }
+
+    /// Samples all the bus lines at once.
+    pub fn read(&self) -> u16 {
+        (self.port.read() & self.mask()) >> self.first_pin
+    }
+
+    /// Switches the bus lines to output mode, for bidirectional buses.
+    pub fn set_output(&self) -> Result<(), String> {
+        self.set_mode(1)
+    }
+
+    /// Switches the bus lines to input mode, for bidirectional buses.
+    pub fn set_input(&self) -> Result<(), String> {
+        self.set_mode(0)
+    }
+
+    /// Fails without touching MODER if any bus line is locked.
+    fn set_mode(&self, mode: u32) -> Result<(), String> {
+        if (self.port.locked_pins() & self.mask()) != 0 {
+            return Err("The pin configuration is locked".to_string())
+        }
+        let mut value = 0;
+        let mut mask = 0;
+        for pin in self.first_pin..(self.first_pin + self.width) {
+            value |= mode << (pin * 2);
+            mask |= 3 << (pin * 2);
+        }
+        unsafe {
+            (*self.port.base_address).mode.update(value, mask);
+        }
+        Ok(())
+    }
+}
+
+pub struct PinPeripheral<'a> {
+    pub port: &'a PortPeripheral,
+    pub pin: u32,
+    pub mode: Mode,
+    pub speed: Frequency,
+    pub pull_side: PullSide,
+}
+
+unsafe impl<'a> Sync for PinPeripheral<'a> {}
+
+impl<'a> PinPeripheral<'a> {
+    pub fn is_locked(&self) -> bool {
+        (self.port.locked_pins() & (1 << self.pin)) != 0
+    }
+
+    /// Freezes this pin's configuration until the next reset.
+    pub fn lock(&self) -> Result<(), String> {
+        if 15 < self.pin {
+            return Err("Invalid pin number".to_string())
+        }
+        self.port.lock(1 << self.pin)
+    }
+}
+
+impl<'a> Peripheral for PinPeripheral<'a> {
+    fn init(&self) -> Result<(), String> {
+        if 15 < self.pin {
+            return Err("Invalid pin number".to_string())
+        }
+
+        let index = match self.port.index() {
+            Some(index) => index,
+            None => return Err("Invalid GPIO port clock".to_string())
+        };
+        if !claim_pin(index, self.pin) {
+            return Err("This pin is already in use".to_string())
+        }
+        if let Err(msg) = self.port.init() {
+            set_pin_in_use(index, self.pin, false);
+            return Err(msg)
+        }
+        if self.is_locked() {
+            set_pin_in_use(index, self.pin, false);
+            return Err("The pin configuration is locked".to_string())
+        }
+
+        let (mode, otype, af, state) = match self.mode {
+            Mode::In => (0, 0, 0, false),
+            Mode::Out(otype, state) => (1, otype as u16, 0, state),
+            Mode::AlternateFunction(af) => (2, 0, af as u32, false),
+            Mode::Analog => { (3, 0, 0, false) }
+        };
+
+        unsafe {
+            configure_pin(&mut *self.port.base_address, self.pin, mode, otype, self.speed, self.pull_side, state, af);
+        }
+
+        Ok(())
+    }
+
+    /// Puts the pin back in its reset state (floating input) and gates the
+    /// port clock if it was the last pin in use.
+    fn deinit(&self) -> Result<(), String> {
+        if 15 < self.pin {
+            return Err("Invalid pin number".to_string())
+        }
+        if self.is_locked() {
+            return Err("The pin configuration is locked".to_string())
+        }
+
+        unsafe {
+            configure_pin(&mut *self.port.base_address, self.pin, 0, 0, Frequency::F2MHz, PullSide::None, false, 0);
+        }
+
+        if self.port.set_pin_in_use(self.pin, false) == 0 {
+            return self.port.deinit()
+        }
+        Ok(())
+    }
+}
+
+pub struct In<'a> {
+    periph: &'a PinPeripheral<'a>
+}
+
+impl<'a> IInput for In<'a> {
+    fn read(&self) -> bool {
+        let mask = 1 << self.periph.pin;
+        unsafe {
+            ((*self.periph.port.base_address).input_data.read() & mask) == mask
+        }
+    }
+}
+
+impl<'a> Drop for In<'a> {
+    fn drop(&mut self) {
+    }
+}
+
+pub struct Out<'a> {
+    periph: &'a PinPeripheral<'a>
+}
+
+impl<'a> Out<'a> {
+    pub fn from(f: &'a PinPeripheral<'a>) -> Out<'a> {
+        Out {
+            periph: f
+        }
+    }
+}
+
+impl<'a> IOutput for Out<'a> {
+    fn get_command(&self) -> bool {
+        let mask = 1 << self.periph.pin;
+        unsafe {
+            ((*self.periph.port.base_address).output_data.read() & mask) == mask
+        }
+    }
+    fn write(&mut self, command: bool) -> bool {
+        let mask = 1 << self.periph.pin;
+        unsafe {
+            if command {
+                (*self.periph.port.base_address).bit_set_reset.write(mask as u32);
+            } else {
+                (*self.periph.port.base_address).bit_set_reset.write((mask as u32) << 16);
+            }
+            ((*self.periph.port.base_address).output_data.read() & mask) == mask
+        }
+    }
+}
+
+impl<'a> Drop for Out<'a> {
+    fn drop(&mut self) {
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // register indexes in the fake port block
+    const MODER: usize = 0;
+    const OTYPER: usize = 1;
+    const OSPEEDR: usize = 2;
+    const PUPDR: usize = 3;
+    const IDR: usize = 4;
+    const ODR: usize = 5;
+    const BSRR: usize = 6;
+    const LCKR: usize = 7;
+    const AFRL: usize = 8;
+    const AFRH: usize = 9;
+    // AHB1ENR in the fake RCC block
+    const AHB1ENR: usize = 12;
+
+    /// Fake register blocks of a port and of the RCC.
+    struct Fake {
+        port: [u32; 10],
+        rcc: [u32; 36]
+    }
+    impl Fake {
+        fn new() -> Fake {
+            Fake {
+                port: [0; 10],
+                rcc: [0; 36]
+            }
+        }
+        fn port(&mut self, clock: rcc::Clock) -> PortPeripheral {
+            PortPeripheral {
+                base_address: self.port.as_mut_ptr() as *mut PortRegisters,
+                clock: rcc::RCCPeripheral {
+                    rcc: self.rcc.as_mut_ptr() as *mut rcc::RCCRegisters,
+                    clock: clock
+                }
+            }
+        }
+    }
+
+    fn pin<'a>(port: &'a PortPeripheral, pin: u32, mode: Mode) -> PinPeripheral<'a> {
+        PinPeripheral {
+            port: port,
+            pin: pin,
+            mode: mode,
+            speed: Frequency::F50MHz,
+            pull_side: PullSide::Up
+        }
+    }
+
+    #[test]
+    fn af_position_splits_at_pin_8() {
+        assert_eq!(af_position(0), (false, 0));
+        assert_eq!(af_position(7), (false, 28));
+        assert_eq!(af_position(8), (true, 0));
+        assert_eq!(af_position(15), (true, 28));
+    }
+
+    #[test]
+    fn port_index_from_clock() {
+        let mut fake = Fake::new();
+        assert_eq!(fake.port(rcc::Clock::GPIOA).index(), Some(0));
+        assert_eq!(fake.port(rcc::Clock::GPIOI).index(), Some(8));
+        assert_eq!(fake.port(rcc::Clock::SPI1).index(), None);
+        assert!(fake.port(rcc::Clock::SPI1).init().is_err());
+    }
+
+    #[test]
+    fn pin_init_programs_its_fields() {
+        let mut fake = Fake::new();
+        let port = fake.port(rcc::Clock::GPIOB);
+        pin(&port, 3, Mode::AlternateFunction(AlternateFunction::AF5)).init().unwrap();
+        pin(&port, 10, Mode::AlternateFunction(AlternateFunction::AF7)).init().unwrap();
+        pin(&port, 12, Mode::Out(OutputType::OpenDrain, true)).init().unwrap();
+
+        assert_eq!(fake.port[MODER], (2 << 6) | (2 << 20) | (1 << 24));
+        assert_eq!(fake.port[OTYPER] & 0xFFFF, 1 << 12);
+        assert_eq!(fake.port[OSPEEDR], (2 << 6) | (2 << 20) | (2 << 24));
+        assert_eq!(fake.port[PUPDR], (1 << 6) | (1 << 20) | (1 << 24));
+        assert_eq!(fake.port[ODR] & 0xFFFF, 1 << 12);
+        // pins 0-7 in AFRL, 8-15 in AFRH
+        assert_eq!(fake.port[AFRL], 5 << 12);
+        assert_eq!(fake.port[AFRH], 7 << 8);
+        // GPIOB clock enabled
+        assert_eq!(fake.rcc[AHB1ENR], 1 << 1);
+    }
+
+    #[test]
+    fn pin_deinit_restores_reset_state() {
+        let mut fake = Fake::new();
+        let port = fake.port(rcc::Clock::GPIOC);
+        let af = pin(&port, 9, Mode::AlternateFunction(AlternateFunction::AF3));
+        let out = pin(&port, 2, Mode::Out(OutputType::OpenDrain, true));
+        af.init().unwrap();
+        out.init().unwrap();
+        assert_eq!(port.pins_in_use(), (1 << 9) | (1 << 2));
+
+        af.deinit().unwrap();
+        assert_eq!(fake.port[AFRH], 0);
+        assert_eq!(fake.port[MODER], 1 << 4);
+        // the port is still in use
+        assert!(port.deinit().is_err());
+        assert_eq!(fake.rcc[AHB1ENR], 1 << 2);
+
+        out.deinit().unwrap();
+        assert_eq!(port.pins_in_use(), 0);
+        for register in [MODER, OTYPER, OSPEEDR, PUPDR, ODR, AFRL, AFRH].iter() {
+            assert_eq!(fake.port[*register], 0);
+        }
+        // the last pin gated the port clock
+        assert_eq!(fake.rcc[AHB1ENR], 0);
+    }
+
+    #[test]
+    fn pin_init_claims_the_pin() {
+        let mut fake = Fake::new();
+        let port = fake.port(rcc::Clock::GPIOE);
+        let first = pin(&port, 4, Mode::In);
+        first.init().unwrap();
+        assert!(pin(&port, 4, Mode::Analog).init().is_err());
+        assert_eq!(fake.port[MODER], 0);
+
+        // a locked pin is not left claimed
+        fake.port[LCKR] = 1 << 5;
+        assert!(pin(&port, 5, Mode::Analog).init().is_err());
+        assert_eq!(port.pins_in_use(), 1 << 4);
+
+        first.deinit().unwrap();
+        assert_eq!(port.pins_in_use(), 0);
+    }
+
+    #[test]
+    fn set_reset_is_a_single_bsrr_write() {
+        let mut fake = Fake::new();
+        let port = fake.port(rcc::Clock::GPIOF);
+        port.set_reset(0x0003, 0x0300);
+        assert_eq!(fake.port[BSRR], 0x0300_0003);
+        // set wins: the pin is in both halves and BSx has priority in hardware
+        port.set_reset(0x0001, 0x0001);
+        assert_eq!(fake.port[BSRR], 0x0001_0001);
+        port.write_masked(0x00F0, 0x0050);
+        assert_eq!(fake.port[BSRR], 0x00A0_0050);
+    }
+
+    #[test]
+    fn parallel_bus_shifts_its_lines() {
+        let mut fake = Fake::new();
+        let port = fake.port(rcc::Clock::GPIOF);
+        assert!(ParallelBus::new(&port, 12, 5).is_err());
+        assert!(ParallelBus::new(&port, 0, 0).is_err());
+        let bus = ParallelBus::new(&port, 4, 8).unwrap();
+        assert_eq!(bus.mask(), 0x0FF0);
+        assert_eq!(ParallelBus::new(&port, 0, 16).unwrap().mask(), 0xFFFF);
+
+        bus.write(0x00A5);
+        assert_eq!(fake.port[BSRR], (0x05A0 << 16) | 0x0A50);
+        // bits beyond the bus width are dropped
+        bus.write(0x01FF);
+        assert_eq!(fake.port[BSRR], 0x0FF0);
+
+        fake.port[IDR] = 0xF5AF;
+        assert_eq!(bus.read(), 0x5A);
+    }
+
+    #[test]
+    fn parallel_bus_switches_direction() {
+        let mut fake = Fake::new();
+        let port = fake.port(rcc::Clock::GPIOF);
+        let bus = ParallelBus::new(&port, 2, 3).unwrap();
+        fake.port[MODER] = 0xC000_0003;
+        bus.set_output().unwrap();
+        assert_eq!(fake.port[MODER], 0xC000_0003 | (0x15 << 4));
+        bus.set_input().unwrap();
+        assert_eq!(fake.port[MODER], 0xC000_0003);
+
+        // a locked bus line leaves MODER untouched
+        fake.port[LCKR] = 1 << 3;
+        assert!(bus.set_output().is_err());
+        assert_eq!(fake.port[MODER], 0xC000_0003);
+    }
+}
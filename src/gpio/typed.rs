@@ -0,0 +1,327 @@
+//! Typestate GPIO pins.
+//!
+//! `Pin<PORT, N, MODE>` carries its port, number and mode in its type and has
+//! no runtime representation. Pins are taken once with `Pin::take` and change
+//! mode by value, so a pin can only be driven while in output mode and only be
+//! switched to an alternate function it actually has:
+//!
+//! ```ignore
+//! let led = Pin::<PD, P12, Input>::take().unwrap().into_push_pull_output(false);
+//! let tx = Pin::<PA, P9, Input>::take().unwrap().into_alternate::<AF7>();
+//! ```
+
+use core::marker::PhantomData;
+
+use collections::string::String;
+use collections::string::ToString;
+
+use silica::peripheral::gpio::{Input as IInput, Output as IOutput};
+
+use rcc;
+use Peripheral;
+use super::{PortRegisters, Frequency, PullSide, GPIOA_BASE, PORT_SIZE};
+use super::{configure_pin, set_pin_in_use, claim_pin};
+
+pub trait PortId {
+    /// 0 for GPIOA up to 8 for GPIOI.
+    fn index() -> usize;
+    fn clock() -> rcc::Clock;
+}
+
+pub trait PinId {
+    fn number() -> u32;
+}
+
+pub trait AlternateFunctionId {
+    fn number() -> u32;
+}
+
+/// Implemented for the (port, pin) pairs on which alternate function `AF`
+/// is connected to a peripheral.
+pub trait HasAlternate<AF> {}
+
+macro_rules! ports {
+    ($($port:ident: $index:expr, $clock:ident;)*) => {
+        $(
+            pub struct $port;
+            impl PortId for $port {
+                fn index() -> usize { $index }
+                fn clock() -> rcc::Clock { rcc::Clock::$clock }
+            }
+        )*
+    }
+}
+
+macro_rules! numbered {
+    ($tr:ident: $($name:ident = $number:expr),*) => {
+        $(
+            pub struct $name;
+            impl $tr for $name {
+                fn number() -> u32 { $number }
+            }
+        )*
+    }
+}
+
+macro_rules! port_id {
+    (A) => (PA); (B) => (PB); (C) => (PC); (D) => (PD); (E) => (PE);
+    (F) => (PF); (G) => (PG); (H) => (PH); (I) => (PI);
+}
+
+macro_rules! pin_id {
+    (0) => (P0); (1) => (P1); (2) => (P2); (3) => (P3); (4) => (P4); (5) => (P5);
+    (6) => (P6); (7) => (P7); (8) => (P8); (9) => (P9); (10) => (P10); (11) => (P11);
+    (12) => (P12); (13) => (P13); (14) => (P14); (15) => (P15);
+}
+
+macro_rules! alternates {
+    ($($signal:ident: $($port:ident $pin:tt),*; $af:ident;)*) => {
+        $($(
+            impl HasAlternate<$af> for (port_id!($port), pin_id!($pin)) {}
+        )*)*
+    }
+}
+
+ports! {
+    PA: 0, GPIOA;
+    PB: 1, GPIOB;
+    PC: 2, GPIOC;
+    PD: 3, GPIOD;
+    PE: 4, GPIOE;
+    PF: 5, GPIOF;
+    PG: 6, GPIOG;
+    PH: 7, GPIOH;
+    PI: 8, GPIOI;
+}
+
+numbered!(PinId: P0 = 0, P1 = 1, P2 = 2, P3 = 3, P4 = 4, P5 = 5, P6 = 6, P7 = 7,
+          P8 = 8, P9 = 9, P10 = 10, P11 = 11, P12 = 12, P13 = 13, P14 = 14, P15 = 15);
+
+numbered!(AlternateFunctionId: AF0 = 0, AF1 = 1, AF2 = 2, AF3 = 3, AF4 = 4, AF5 = 5, AF6 = 6,
+          AF7 = 7, AF8 = 8, AF9 = 9, AF10 = 10, AF11 = 11, AF12 = 12, AF13 = 13, AF14 = 14,
+          AF15 = 15);
+
+/// Floating or pulled input, the reset state.
+pub struct Input;
+pub struct Analog;
+pub struct Output<T> {
+    _type: PhantomData<T>
+}
+pub struct PushPull;
+pub struct OpenDrain;
+pub struct Alternate<AF> {
+    _af: PhantomData<AF>
+}
+/// A pin in `MODE` whose configuration is frozen until the next reset.
+pub struct Locked<MODE> {
+    _mode: PhantomData<MODE>
+}
+
+/// Implemented for the modes that can still be reconfigured.
+pub trait Unlocked {}
+impl Unlocked for Input {}
+impl Unlocked for Analog {}
+impl<T> Unlocked for Output<T> {}
+impl<AF> Unlocked for Alternate<AF> {}
+
+af_data!(alternates);
+
+pub struct Pin<PORT, N, MODE> {
+    _port: PhantomData<PORT>,
+    _pin: PhantomData<N>,
+    _mode: PhantomData<MODE>
+}
+
+fn new_pin<PORT, N, MODE>() -> Pin<PORT, N, MODE> {
+    Pin {
+        _port: PhantomData,
+        _pin: PhantomData,
+        _mode: PhantomData
+    }
+}
+
+fn port_clock(clock: rcc::Clock) -> rcc::RCCPeripheral {
+    rcc::RCCPeripheral {
+        rcc: unsafe { rcc::rcc_get() },
+        clock: clock
+    }
+}
+
+impl<PORT: PortId, N: PinId> Pin<PORT, N, Input> {
+    /// Takes ownership of the pin and puts it in input mode. Fails if the pin
+    /// is already used, typed or through a `PinPeripheral`, or locked.
+    pub fn take() -> Result<Pin<PORT, N, Input>, String> {
+        if !claim_pin(PORT::index(), N::number()) {
+            return Err("This pin is already in use".to_string())
+        }
+        if let Err(msg) = port_clock(PORT::clock()).init() {
+            set_pin_in_use(PORT::index(), N::number(), false);
+            return Err(msg)
+        }
+        let pin: Pin<PORT, N, Input> = new_pin();
+        if (pin.registers().lock.read() & (1 << N::number())) != 0 {
+            set_pin_in_use(PORT::index(), N::number(), false);
+            return Err("The pin configuration is locked".to_string())
+        }
+        pin.configure(0, 0, 0, false);
+        Ok(pin)
+    }
+}
+
+impl<PORT: PortId, N: PinId, MODE> Pin<PORT, N, MODE> {
+    fn registers(&self) -> &'static mut PortRegisters {
+        unsafe { &mut *((GPIOA_BASE + PORT::index() * PORT_SIZE) as *mut PortRegisters) }
+    }
+
+    fn mask(&self) -> u16 {
+        1 << N::number()
+    }
+
+    /// Sets the mode, keeping speed and pull settings.
+    fn configure(&self, mode: u32, otype: u16, af: u32, state: bool) {
+        let regs = self.registers();
+        let twobit_shift = N::number() * 2;
+        let speed = match (regs.output_speed.read() >> twobit_shift) & 3 {
+            0 => Frequency::F2MHz,
+            1 => Frequency::F20MHz,
+            2 => Frequency::F50MHz,
+            _ => Frequency::F100MHz
+        };
+        let pull_side = match (regs.pu_pd.read() >> twobit_shift) & 3 {
+            0 => PullSide::None,
+            1 => PullSide::Up,
+            2 => PullSide::Down,
+            _ => PullSide::Both
+        };
+        configure_pin(regs, N::number(), mode, otype, speed, pull_side, state, af);
+    }
+}
+
+impl<PORT: PortId, N: PinId, MODE: Unlocked> Pin<PORT, N, MODE> {
+    pub fn set_speed(&mut self, speed: Frequency) {
+        let shift = N::number() * 2;
+        self.registers().output_speed.update((speed as u32) << shift, 3 << shift);
+    }
+
+    pub fn set_pull(&mut self, pull_side: PullSide) {
+        let shift = N::number() * 2;
+        self.registers().pu_pd.update((pull_side as u32) << shift, 3 << shift);
+    }
+
+    pub fn into_input(self) -> Pin<PORT, N, Input> {
+        self.configure(0, 0, 0, false);
+        new_pin()
+    }
+
+    pub fn into_analog(self) -> Pin<PORT, N, Analog> {
+        self.configure(3, 0, 0, false);
+        new_pin()
+    }
+
+    pub fn into_push_pull_output(self, state: bool) -> Pin<PORT, N, Output<PushPull>> {
+        self.configure(1, 0, 0, state);
+        new_pin()
+    }
+
+    pub fn into_open_drain_output(self, state: bool) -> Pin<PORT, N, Output<OpenDrain>> {
+        self.configure(1, 1, 0, state);
+        new_pin()
+    }
+
+    /// Only compiles for alternate functions connected to this pin.
+    pub fn into_alternate<AF: AlternateFunctionId>(self) -> Pin<PORT, N, Alternate<AF>>
+        where (PORT, N): HasAlternate<AF> {
+        self.configure(2, 0, AF::number(), false);
+        new_pin()
+    }
+
+    /// Freezes the pin configuration until the next reset. The locked pin
+    /// keeps its mode but can no longer be reconfigured nor released. On
+    /// failure the pin stays claimed as its configuration is unknown.
+    pub fn lock(self) -> Result<Pin<PORT, N, Locked<MODE>>, String> {
+        if let Err(msg) = self.registers().lock_pins(self.mask()) {
+            return Err(msg)
+        }
+        Ok(new_pin())
+    }
+
+    /// Puts the pin back in its reset state and gives it back. The port clock
+    /// is gated if no other pin of the port is in use.
+    pub fn release(self) -> Result<(), String> {
+        if (self.registers().lock.read() & (self.mask() as u32)) != 0 {
+            return Err("The pin configuration is locked".to_string())
+        }
+        configure_pin(self.registers(), N::number(), 0, 0, Frequency::F2MHz, PullSide::None, false, 0);
+        if set_pin_in_use(PORT::index(), N::number(), false) == 0 {
+            return port_clock(PORT::clock()).deinit()
+        }
+        Ok(())
+    }
+}
+
+impl<PORT: PortId, N: PinId, MODE> Pin<PORT, N, MODE> {
+    fn read_input(&self) -> bool {
+        (self.registers().input_data.read() & self.mask()) != 0
+    }
+
+    fn get_output(&self) -> bool {
+        (self.registers().output_data.read() & self.mask()) != 0
+    }
+
+    fn write_output(&mut self, command: bool) -> bool {
+        let mask = self.mask();
+        let regs = self.registers();
+        if command {
+            regs.bit_set_reset.write(mask as u32);
+        } else {
+            regs.bit_set_reset.write((mask as u32) << 16);
+        }
+        (regs.output_data.read() & mask) != 0
+    }
+}
+
+impl<PORT: PortId, N: PinId> IInput for Pin<PORT, N, Input> {
+    fn read(&self) -> bool {
+        self.read_input()
+    }
+}
+
+impl<PORT: PortId, N: PinId> IInput for Pin<PORT, N, Locked<Input>> {
+    fn read(&self) -> bool {
+        self.read_input()
+    }
+}
+
+impl<PORT: PortId, N: PinId, T> IOutput for Pin<PORT, N, Output<T>> {
+    fn get_command(&self) -> bool {
+        self.get_output()
+    }
+    fn write(&mut self, command: bool) -> bool {
+        self.write_output(command)
+    }
+}
+
+impl<PORT: PortId, N: PinId, T> IOutput for Pin<PORT, N, Locked<Output<T>>> {
+    fn get_command(&self) -> bool {
+        self.get_output()
+    }
+    fn write(&mut self, command: bool) -> bool {
+        self.write_output(command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn has_alternate<PIN: HasAlternate<AF>, AF>() {}
+
+    #[test]
+    fn alternates_follow_the_af_table() {
+        has_alternate::<(PA, P8), AF0>();
+        has_alternate::<(PB, P13), AF5>();
+        has_alternate::<(PA, P12), AF10>();
+        has_alternate::<(PD, P14), AF12>();
+        has_alternate::<(PI, P11), AF15>();
+    }
+}
@@ -29,16 +29,22 @@ macro_rules! init_peripheral {
 
 /// GPIO control module
 pub mod gpio;
+/// EXTI (external interrupt) control module
+pub mod exti;
 /// DMA control module
 pub mod dma;
 /// USART (and UART) control module
 pub mod usart;
+/// SPI control module
+pub mod spi;
 /// Timer control module
 pub mod timer;
 /// RCC control module
 pub mod rcc;
 /// Flash control module
 pub mod flash;
+/// SysTick time base module
+pub mod time;
 
 
 use silica_cortexm3::{Exceptions, Handler};
@@ -60,6 +66,56 @@ pub struct AdvancedPeripheralBus {
     pub clock_prescaler: u32
 }
 
+/// Runs `f` with interrupts disabled and restores the previous state after.
+#[cfg(target_arch = "arm")]
+pub fn critical_section<R, F: FnOnce() -> R>(f: F) -> R {
+    let primask: u32;
+    unsafe {
+        asm!("mrs $0, primask\n cpsid i" : "=r"(primask) ::: "volatile");
+    }
+    let res = f();
+    if (primask & 1) == 0 {
+        unsafe {
+            asm!("cpsie i" :::: "volatile");
+        }
+    }
+    res
+}
+/// Host builds (unit tests) have no interrupts to mask.
+#[cfg(not(target_arch = "arm"))]
+pub fn critical_section<R, F: FnOnce() -> R>(f: F) -> R {
+    f()
+}
+
+/// Number of the exception being handled, 0 in thread mode.
+#[cfg(target_arch = "arm")]
+pub fn active_exception() -> u32 {
+    let isr_id: u32;
+    unsafe {
+        asm!("mrs $0, ipsr": "=r"(isr_id));
+    }
+    isr_id
+}
+/// Host builds always run in thread mode.
+#[cfg(not(target_arch = "arm"))]
+pub fn active_exception() -> u32 {
+    0
+}
+
+/// Whether interrupts are masked by PRIMASK.
+#[cfg(target_arch = "arm")]
+pub fn interrupts_masked() -> bool {
+    let primask: u32;
+    unsafe {
+        asm!("mrs $0, primask" : "=r"(primask));
+    }
+    (primask & 1) == 1
+}
+#[cfg(not(target_arch = "arm"))]
+pub fn interrupts_masked() -> bool {
+    false
+}
+
 /// Peripheral trait
 pub trait Peripheral {
     fn init(&self) -> Result<(), String>;
@@ -97,9 +153,11 @@ pub unsafe extern "C" fn hf_handler() {
 pub unsafe extern "C" fn pendsv() {
 }
 pub unsafe extern "C" fn systick() {
+    time::on_tick();
 }
 
 #[allow(non_camel_case_types)]
+#[derive(Copy, Clone)]
 pub enum IRQType {
     WWDG            = 0,
     PVD             = 1,
@@ -183,6 +241,22 @@ pub enum IRQType {
     CRYP            = 79,
     HASH_RNG        = 80,
 }
+impl IRQType {
+    /// Enables this interrupt line in the NVIC.
+    pub fn enable(self) {
+        let irq = self as usize;
+        unsafe {
+            core::intrinsics::volatile_store((0xE000E100 + 4 * (irq >> 5)) as *mut u32, 1 << (irq & 0x1F));
+        }
+    }
+    /// Disables this interrupt line in the NVIC.
+    pub fn disable(self) {
+        let irq = self as usize;
+        unsafe {
+            core::intrinsics::volatile_store((0xE000E180 + 4 * (irq >> 5)) as *mut u32, 1 << (irq & 0x1F));
+        }
+    }
+}
 
 #[no_mangle]
 #[linkage = "external"]
@@ -205,65 +279,86 @@ pub static EXCEPTIONS: Exceptions = Exceptions {
 #[no_mangle]
 #[linkage = "external"]
 #[link_section = ".text.isr"]
-pub static ISRVEC: [Handler;60] = [
-    default_handler,   // wwdg
+pub static ISRVEC: [Handler;81] = [
+    default_handler,   // WWDG
     default_handler,   // PVD
-    default_handler,   // TAMPER
-    default_handler,   // RTC
+    default_handler,   // TAMP_STAMP
+    default_handler,   // RTC_WKUP
     default_handler,   // FLASH
     default_handler,   // RCC
-    default_handler,   // EXTI0
-    default_handler,   // EXTI1
-    default_handler,   // EXTI2
-    default_handler,   // EXTI3
-    default_handler,   // EXTI4
-    default_handler,   // DMA1_Channel1
-    default_handler,   // DMA1_Channel2
-    default_handler,   // DMA1_Channel3
-    default_handler,   // DMA1_Channel4
-    default_handler,   // DMA1_Channel5
-    default_handler,   // DMA1_Channel6
-    default_handler,   // DMA1_Channel7
-    default_handler,   // ADC1_2
-    default_handler,   // USB_HP_CAN_TX
-    default_handler,   // USN_LP_CAN_RX0
-    default_handler,   // CAN_RX1
-    default_handler,   // CAN_SCE
-    default_handler,   // EXTI9_5
-    default_handler,   // TIM1_BRK
-    default_handler,   // TIM1_UP
-    default_handler,   // TIM1_TRG_COM
-    default_handler,   // TIM1_CC
-    default_handler,   // TIM2
-    default_handler,   // TIM3
-    default_handler,   // TIM4
+    exti::exti0_handler,   // EXTI0
+    exti::exti1_handler,   // EXTI1
+    exti::exti2_handler,   // EXTI2
+    exti::exti3_handler,   // EXTI3
+    exti::exti4_handler,   // EXTI4
+    dma::dma1_stream0_handler,   // DMA1_Stream0
+    dma::dma1_stream1_handler,   // DMA1_Stream1
+    dma::dma1_stream2_handler,   // DMA1_Stream2
+    dma::dma1_stream3_handler,   // DMA1_Stream3
+    dma::dma1_stream4_handler,   // DMA1_Stream4
+    dma::dma1_stream5_handler,   // DMA1_Stream5
+    dma::dma1_stream6_handler,   // DMA1_Stream6
+    default_handler,   // ADC
+    default_handler,   // CAN1_TX
+    default_handler,   // CAN1_RX0
+    default_handler,   // CAN1_RX1
+    default_handler,   // CAN1_SCE
+    exti::exti9_5_handler,   // EXTI9_5
+    timer::tim1_brk_tim9_handler,   // TIM1_BRK_TIM9
+    timer::tim1_up_tim10_handler,   // TIM1_UP_TIM10
+    timer::tim1_trg_com_tim11_handler,   // TIM1_TRG_COM_TIM11
+    timer::tim1_cc_handler,   // TIM1_CC
+    timer::tim2_handler,   // TIM2
+    timer::tim3_handler,   // TIM3
+    timer::tim4_handler,   // TIM4
     default_handler,   // I2C1_EV
     default_handler,   // I2C1_ER
     default_handler,   // I2C2_EV
     default_handler,   // I2C2_ER
-    default_handler,   // SPI1
-    default_handler,   // SPI2
-    default_handler,   // USART1
-    default_handler,   // USART2
-    default_handler,   // USART3
-    default_handler,   // EXTI15_10
-    default_handler,   // RTCAlarm
-    default_handler,   // USBWakeUp
-    default_handler,   // TIIM8_BRK
-    default_handler,   // TIIM8_UP
-    default_handler,   // TIIM8_TRG_COM
-    default_handler,   // TIIM8_CC
-    default_handler,   // ADC3
+    spi::spi1_handler,   // SPI1
+    spi::spi2_handler,   // SPI2
+    usart::usart1_handler,   // USART1
+    usart::usart2_handler,   // USART2
+    usart::usart3_handler,   // USART3
+    exti::exti15_10_handler,   // EXTI15_10
+    default_handler,   // RTC_Alarm
+    default_handler,   // OTG_FS_WKUP
+    timer::tim8_brk_tim12_handler,   // TIM8_BRK_TIM12
+    timer::tim8_up_tim13_handler,   // TIM8_UP_TIM13
+    timer::tim8_trg_com_tim14_handler,   // TIM8_TRG_COM_TIM14
+    timer::tim8_cc_handler,   // TIM8_CC
+    dma::dma1_stream7_handler,   // DMA1_Stream7
     default_handler,   // FSMC
     default_handler,   // SDIO
-    default_handler,   // TIM5
-    default_handler,   // SPI3
-    default_handler,   // UART4
-    default_handler,   // UART5
-    default_handler,   // TIM6
-    default_handler,   // TIM7
-    default_handler,   // DMA1_Channel1
-    default_handler,   // DMA1_Channel2
-    default_handler,   // DMA1_Channel3
-    default_handler,   // DMA1_Channel4_5
+    timer::tim5_handler,   // TIM5
+    spi::spi3_handler,   // SPI3
+    usart::uart4_handler,   // UART4
+    usart::uart5_handler,   // UART5
+    timer::tim6_dac_handler,   // TIM6_DAC
+    timer::tim7_handler,   // TIM7
+    dma::dma2_stream0_handler,   // DMA2_Stream0
+    dma::dma2_stream1_handler,   // DMA2_Stream1
+    dma::dma2_stream2_handler,   // DMA2_Stream2
+    dma::dma2_stream3_handler,   // DMA2_Stream3
+    dma::dma2_stream4_handler,   // DMA2_Stream4
+    default_handler,   // ETH
+    default_handler,   // ETH_WKUP
+    default_handler,   // CAN2_TX
+    default_handler,   // CAN2_RX0
+    default_handler,   // CAN2_RX1
+    default_handler,   // CAN2_SCE
+    default_handler,   // OTG_FS
+    dma::dma2_stream5_handler,   // DMA2_Stream5
+    dma::dma2_stream6_handler,   // DMA2_Stream6
+    dma::dma2_stream7_handler,   // DMA2_Stream7
+    usart::usart6_handler,   // USART6
+    default_handler,   // I2C3_EV
+    default_handler,   // I2C3_ER
+    default_handler,   // OTG_HS_EP1_OUT
+    default_handler,   // OTG_HS_EP1_IN
+    default_handler,   // OTG_HS_WKUP
+    default_handler,   // OTG_HS
+    default_handler,   // DCMI
+    default_handler,   // CRYP
+    default_handler,   // HASH_RNG
 ];
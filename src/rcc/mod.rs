@@ -1,15 +1,66 @@
 use collections::string::String;
 
 mod flags;
+mod pll;
 
 pub use self::flags::*;
+pub use self::pll::*;
 
 use registers::*;
 use Peripheral;
+use critical_section;
+use flash::{self, VoltageRange};
+use time;
 
 const APBAHB_PRESCALER_TABLE: [u8; 16] = [0, 0, 0, 0, 1, 2, 3, 4, 1, 2, 3, 4, 6, 7, 8, 9];
 
-pub static mut SYSCLOCK: usize = 8_000_000;
+/// Frequencies of the system, bus and timer clocks, in Hz.
+#[derive(Copy, Clone)]
+pub struct Clocks {
+    pub sysclk: usize,
+    pub hclk: usize,
+    pub pclk1: usize,
+    pub pclk2: usize,
+    /// Clock of the timers on APB1, twice PCLK1 when APB1 is divided.
+    pub timclk1: usize,
+    /// Clock of the timers on APB2, twice PCLK2 when APB2 is divided.
+    pub timclk2: usize
+}
+impl Clocks {
+    /// Derives the bus clocks from SYSCLK and the CFGR register value.
+    pub fn from_config(sysclk: usize, cfgr: u32) -> Clocks {
+        let hpre = APBAHB_PRESCALER_TABLE[((cfgr & CFGR_HPRE_MASK) >> 4) as usize];
+        let ppre1 = APBAHB_PRESCALER_TABLE[((cfgr & CFGR_PPRE1_MASK) >> CFGR_PPRE1_SHIFT) as usize];
+        let ppre2 = APBAHB_PRESCALER_TABLE[((cfgr & CFGR_PPRE2_MASK) >> CFGR_PPRE2_SHIFT) as usize];
+
+        let hclk = sysclk >> hpre;
+        let pclk1 = hclk >> ppre1;
+        let pclk2 = hclk >> ppre2;
+        Clocks {
+            sysclk: sysclk,
+            hclk: hclk,
+            pclk1: pclk1,
+            pclk2: pclk2,
+            timclk1: if ppre1 == 0 { pclk1 } else { pclk1 * 2 },
+            timclk2: if ppre2 == 0 { pclk2 } else { pclk2 * 2 }
+        }
+    }
+}
+
+// reset state: everything runs from HSI
+static mut CLOCKS: Clocks = Clocks {
+    sysclk: 16_000_000,
+    hclk: 16_000_000,
+    pclk1: 16_000_000,
+    pclk2: 16_000_000,
+    timclk1: 16_000_000,
+    timclk2: 16_000_000
+};
+
+/// Returns the clock frequencies recorded by `system_init`.
+pub fn clocks() -> Clocks {
+    critical_section(|| unsafe { CLOCKS })
+}
 
 // _ = reserved
 // r = ro
@@ -85,21 +136,35 @@ pub enum ClockSelection {
     HSE(u32),
     HSI
 }
+impl ClockSelection {
+    /// Frequency of the selected oscillator, which is also the PLL input.
+    pub fn frequency(&self) -> u32 {
+        match *self {
+            ClockSelection::HSE(clk) => clk,
+            ClockSelection::HSI => 16_000_000
+        }
+    }
+}
 
+/// PLL setting: M, N, P, Q. See `compute_pll` to get them from frequencies.
 pub enum PLL {
     Off,
-    On(u8, u8, u8, u8)
+    On(u8, u16, u8, u8)
 }
 
-fn wait_for(status: &Fn() -> bool) -> bool {
-    let mut ret = false;
-    let mut timeout_cnt = 0x500;
-
-    while (!ret) && (timeout_cnt != 0) {
-        ret = status();
-        timeout_cnt -= 1;
-    }
-    ret
+/// Oscillator start-up timeout, in ms.
+const OSC_STARTUP_TIMEOUT: u32 = 100;
+/// PLL lock timeout, in ms.
+const PLL_LOCK_TIMEOUT: u32 = 10;
+/// System clock switch timeout, in ms.
+const CLOCK_SWITCH_TIMEOUT: u32 = 10;
+
+/// Restarts the time base from the current HCLK.
+fn retune_time_base(clocks: Clocks) -> Result<(), &'static str> {
+    critical_section(|| unsafe {
+        CLOCKS = clocks;
+    });
+    time::init(clocks.hclk as u32)
 }
 
 // pll input
@@ -113,7 +178,8 @@ fn wait_for(status: &Fn() -> bool) -> bool {
 pub fn system_init(clksrc: ClockSelection, pll: PLL,
                    hpre: CFGR_HPrescaler,
                    apb1: CFGR_PPrescaler1,
-                   apb2: CFGR_PPrescaler2) -> Result<u32, &'static str> {
+                   apb2: CFGR_PPrescaler2,
+                   voltage: VoltageRange) -> Result<u32, &'static str> {
     let mut sysclock = 16_000_000;
     let mut source = CFGR_SW_HSI;
     let mut source_status = CFGR_SWS_HSI;
@@ -126,6 +192,9 @@ pub fn system_init(clksrc: ClockSelection, pll: PLL,
 
     // reset RCC config
     rcc.config.write(0);
+    if let Err(msg) = retune_time_base(Clocks::from_config(16_000_000, 0)) {
+        return Err(msg);
+    }
 
     // reset HSEON, CSSON & PLLON
     rcc.control.update(0, CR_HSEON | CR_CSSON | CR_PLLON);
@@ -154,8 +223,7 @@ pub fn system_init(clksrc: ClockSelection, pll: PLL,
         }
     };
 
-    let status = wait_for(&|| (rcc.control.read() & rdyflag) ==  rdyflag);
-    if !status {
+    if !time::wait_for(OSC_STARTUP_TIMEOUT, || (rcc.control.read() & rdyflag) == rdyflag) {
         return Err("Failed to initialize the clock.");
     }
 
@@ -163,41 +231,20 @@ pub fn system_init(clksrc: ClockSelection, pll: PLL,
         (hpre as u32) | (apb1 as u32) | (apb2 as u32),
         CFGR_HPRE_MASK | CFGR_PPRE1_MASK | CFGR_PPRE2_MASK
     );
+    // still running from HSI, but HCLK may now be divided
+    if let Err(msg) = retune_time_base(Clocks::from_config(16_000_000, rcc.config.read())) {
+        return Err(msg);
+    }
 
     if let PLL::On(M, N, P, Q) = pll {
         let (M, N, P, Q)= (M as u32, N as u32, P as u32, Q as u32);
         source = CFGR_SW_PLL;
         source_status = CFGR_SWS_PLL;
 
-        if (M < 2) || (N < 2) || (Q < 2) {
-            return Err("M, N and Q must be > 2.");
-        }
-
-        if (P != 2) && (P != 4) && (P != 6) && (P != 8) {
-            return Err("P must be 2, 4, 6 or 8.");
-        }
-
-        sysclock /= M;
-
-        if (sysclock < 1_000_000) || (2_000_000 < sysclock ) {
-            return Err("PLL input must be in [1; 2]MHz.");
-        }
-
-        sysclock *= N;
-
-        if (sysclock < 192_000_000) || (432_000_000 < sysclock ) {
-            return Err("VCO output must be in [192; 432]MHz.");
-        }
-
-        let usbotgclk = sysclock / Q;
-        sysclock /= P;
-
-        if 120_000_000 < sysclock {
-            return Err("PLL output clock must not exceed 120MHz.");
-        }
-        if 48_000_000 < usbotgclk {
-            return Err("USB OTG output clock must not exceed 48MHz.");
-        }
+        sysclock = match pll_output(sysclock, M, N, P, Q) {
+            Ok((sysclk, _)) => sysclk,
+            Err(msg) => return Err(msg)
+        };
 
         rcc.pll_config.write(
             M |
@@ -208,28 +255,41 @@ pub fn system_init(clksrc: ClockSelection, pll: PLL,
         );
         rcc.control.update(CR_PLLON, CR_PLLON);
 
-        // no time out here in the peripheral lib .. hm
-        while (rcc.control.read() & CR_PLLRDY) != CR_PLLRDY {}
+        if !time::wait_for(PLL_LOCK_TIMEOUT, || (rcc.control.read() & CR_PLLRDY) == CR_PLLRDY) {
+            return Err("The PLL failed to lock.");
+        }
     }
 
-    // latency should be selected according to power supply voltage & clock freq
-    {
-        use ::flash::*;
-        let flash = unsafe { flash_get() } ;
-        flash.access_control.update(
-            ACR_ICEN | ACR_DCEN | ACR_PRFTEN | 3,
-            ACR_ICEN | ACR_DCEN | ACR_PRFTEN | ACR_LATENCY_MASK
-        );
+    // the flash must be slowed down before the clock gets faster and can only
+    // be sped up once the clock got slower.
+    let clocks = Clocks::from_config(sysclock as usize, rcc.config.read());
+    let ws = match flash::wait_states(voltage, clocks.hclk as u32) {
+        Ok(ws) => ws,
+        Err(msg) => return Err(msg)
+    };
+    let flash = unsafe { flash::flash_get() };
+    let raise_latency = flash.latency() < ws.latency;
+    if raise_latency {
+        if let Err(msg) = flash.set_wait_states(ws) {
+            return Err(msg);
+        }
     }
 
     rcc.config.update(0, CFGR_SW_MASK);
     rcc.config.update(source, CFGR_SW_MASK);
 
-    // no time out here in the peripheral lib .. hm
-    while (rcc.config.read() & CFGR_SWS_MASK) != source_status {}
+    if !time::wait_for(CLOCK_SWITCH_TIMEOUT, || (rcc.config.read() & CFGR_SWS_MASK) == source_status) {
+        return Err("Failed to switch the system clock.");
+    }
+
+    if !raise_latency {
+        if let Err(msg) = flash.set_wait_states(ws) {
+            return Err(msg);
+        }
+    }
 
-    unsafe {
-        SYSCLOCK = sysclock as usize;
+    if let Err(msg) = retune_time_base(clocks) {
+        return Err(msg);
     }
     Ok(sysclock)
 }
@@ -252,11 +312,16 @@ impl RCCPeripheral {
             }
         }
     }
+    /// Returns the frequency of the clock feeding this peripheral.
+    /// Timers get the timer clock of their bus.
     pub fn get_clock(&self) -> usize {
-        unsafe {
-            // critical_section_start
-            SYSCLOCK
-            // critical_section_end
+        let clocks = clocks();
+        match ((self.clock as u8) >> 5, self.clock.is_timer()) {
+            (3, false) => clocks.pclk1,
+            (3, true) => clocks.timclk1,
+            (4, false) => clocks.pclk2,
+            (4, true) => clocks.timclk2,
+            _ => clocks.hclk
         }
     }
 }
@@ -348,6 +413,17 @@ pub enum Clock {
     GPIOB = 0x01,
     GPIOA = 0x00
 }
+impl Clock {
+    pub fn is_timer(self) -> bool {
+        match self {
+            Clock::TIM1 | Clock::TIM2 | Clock::TIM3 | Clock::TIM4 | Clock::TIM5 |
+            Clock::TIM6 | Clock::TIM7 | Clock::TIM8 | Clock::TIM9 | Clock::TIM10 |
+            Clock::TIM11 | Clock::TIM12 | Clock::TIM13 | Clock::TIM14 => true,
+            _ => false
+        }
+    }
+}
+
 pub enum LowPowerLock {
 
 }
@@ -0,0 +1,242 @@
+use super::PLL;
+
+pub const PLL_M_MIN: u32 = 2;
+pub const PLL_M_MAX: u32 = 63;
+pub const PLL_N_MIN: u32 = 192;
+pub const PLL_N_MAX: u32 = 432;
+pub const PLL_Q_MIN: u32 = 2;
+pub const PLL_Q_MAX: u32 = 15;
+pub const PLL_VCO_INPUT_MIN: u32 = 1_000_000;
+pub const PLL_VCO_INPUT_MAX: u32 = 2_000_000;
+pub const PLL_VCO_OUTPUT_MIN: u32 = 192_000_000;
+pub const PLL_VCO_OUTPUT_MAX: u32 = 432_000_000;
+pub const SYSCLK_MAX: u32 = 120_000_000;
+pub const USB_CLOCK: u32 = 48_000_000;
+
+/// PLL dividers found by `compute_pll` and the clocks they produce.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct PLLConfig {
+    pub m: u8,
+    pub n: u16,
+    pub p: u8,
+    pub q: u8,
+    pub sysclk: u32,
+    /// 48MHz domain clock (USB OTG FS, SDIO, RNG).
+    pub usbclk: u32
+}
+impl PLLConfig {
+    pub fn pll(&self) -> PLL {
+        PLL::On(self.m, self.n, self.p, self.q)
+    }
+}
+
+/// Checks an M/N/P/Q setting against the datasheet ranges and returns the
+/// SYSCLK and 48MHz domain clocks it gives from a PLL input of `input` Hz.
+pub fn pll_output(input: u32, m: u32, n: u32, p: u32, q: u32) -> Result<(u32, u32), &'static str> {
+    if (m < PLL_M_MIN) || (PLL_M_MAX < m) {
+        return Err("M must be in [2; 63].");
+    }
+    if (n < PLL_N_MIN) || (PLL_N_MAX < n) {
+        return Err("N must be in [192; 432].");
+    }
+    if (q < PLL_Q_MIN) || (PLL_Q_MAX < q) {
+        return Err("Q must be in [2; 15].");
+    }
+    if (p != 2) && (p != 4) && (p != 6) && (p != 8) {
+        return Err("P must be 2, 4, 6 or 8.");
+    }
+    if (input < PLL_VCO_INPUT_MIN * m) || (PLL_VCO_INPUT_MAX * m < input) {
+        return Err("PLL input must be in [1; 2]MHz.");
+    }
+    // input * N / M, without truncating the VCO input first
+    let vco = ((input as u64) * (n as u64) / (m as u64)) as u32;
+    if (vco < PLL_VCO_OUTPUT_MIN) || (PLL_VCO_OUTPUT_MAX < vco) {
+        return Err("VCO output must be in [192; 432]MHz.");
+    }
+    let (sysclk, usbclk) = (vco / p, vco / q);
+    if SYSCLK_MAX < sysclk {
+        return Err("PLL output clock must not exceed 120MHz.");
+    }
+    if USB_CLOCK < usbclk {
+        return Err("USB OTG output clock must not exceed 48MHz.");
+    }
+    Ok((sysclk, usbclk))
+}
+
+/// Searches the M/N/P/Q space for the setting that gets SYSCLK the closest to
+/// `sysclk` (without going over 120MHz) from a PLL input of `input` Hz.
+///
+/// When `usb48` is set, only settings giving exactly 48MHz on the Q output are
+/// considered. Otherwise Q is chosen to keep that output under 48MHz.
+/// On equal SYSCLK error, the highest VCO input frequency is preferred as it
+/// gives the lowest jitter.
+pub fn compute_pll(input: u32, sysclk: u32, usb48: bool) -> Result<PLLConfig, &'static str> {
+    if (sysclk == 0) || (SYSCLK_MAX < sysclk) {
+        return Err("PLL output clock must be in ]0; 120]MHz.");
+    }
+
+    let mut best: Option<(u32, PLLConfig)> = None;
+
+    for m in PLL_M_MIN..(PLL_M_MAX + 1) {
+        // VCO input is checked on the exact ratio: input/m in [1; 2]MHz
+        if (input < PLL_VCO_INPUT_MIN * m) || (PLL_VCO_INPUT_MAX * m < input) {
+            continue;
+        }
+
+        for n in PLL_N_MIN..(PLL_N_MAX + 1) {
+            let vco = ((input as u64) * (n as u64)) / (m as u64);
+            if (vco < PLL_VCO_OUTPUT_MIN as u64) || ((PLL_VCO_OUTPUT_MAX as u64) < vco) {
+                continue;
+            }
+            let vco = vco as u32;
+
+            let q = if usb48 {
+                if ((input as u64) * (n as u64)) % ((m as u64) * (USB_CLOCK as u64)) != 0 {
+                    continue;
+                }
+                vco / USB_CLOCK
+            } else {
+                let q = (vco + USB_CLOCK - 1) / USB_CLOCK;
+                if q < PLL_Q_MIN { PLL_Q_MIN } else { q }
+            };
+            if (q < PLL_Q_MIN) || (PLL_Q_MAX < q) {
+                continue;
+            }
+
+            for p in [2u32, 4, 6, 8].iter() {
+                let output = vco / p;
+                if SYSCLK_MAX < output {
+                    continue;
+                }
+                let error = if output < sysclk { sysclk - output } else { output - sysclk };
+                let better = match best {
+                    Some((best_error, _)) => error < best_error,
+                    None => true
+                };
+                if better {
+                    best = Some((error, PLLConfig {
+                        m: m as u8,
+                        n: n as u16,
+                        p: *p as u8,
+                        q: q as u8,
+                        sysclk: output,
+                        usbclk: vco / q
+                    }));
+                }
+            }
+        }
+    }
+
+    match best {
+        Some((_, config)) => Ok(config),
+        None => Err("No PLL setting matches these constraints.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pll_output_matches_the_solver() {
+        // 8MHz / 7 is not an integer, the VCO must not be computed from it
+        assert_eq!(pll_output(8_000_000, 7, 210, 2, 5), Ok((120_000_000, 48_000_000)));
+        for &(input, target) in [(8_000_000, 120_000_000), (25_000_000, 120_000_000), (12_000_000, 100_000_000),
+                                 (16_000_000, 96_000_000), (26_000_000, 72_000_000)].iter() {
+            let config = compute_pll(input, target, true).unwrap();
+            let (m, n, p, q) = (config.m as u32, config.n as u32, config.p as u32, config.q as u32);
+            assert_eq!(pll_output(input, m, n, p, q), Ok((config.sysclk, config.usbclk)));
+        }
+    }
+
+    #[test]
+    fn pll_output_rejects_out_of_range_settings() {
+        assert_eq!(pll_output(8_000_000, 1, 210, 2, 5), Err("M must be in [2; 63]."));
+        assert_eq!(pll_output(8_000_000, 8, 100, 2, 5), Err("N must be in [192; 432]."));
+        assert_eq!(pll_output(8_000_000, 8, 240, 3, 5), Err("P must be 2, 4, 6 or 8."));
+        assert_eq!(pll_output(8_000_000, 2, 240, 2, 5), Err("PLL input must be in [1; 2]MHz."));
+        assert_eq!(pll_output(8_000_000, 8, 288, 2, 10), Err("PLL output clock must not exceed 120MHz."));
+        assert_eq!(pll_output(8_000_000, 8, 240, 2, 4), Err("USB OTG output clock must not exceed 48MHz."));
+    }
+
+    fn check_ranges(input: u32, config: &PLLConfig) {
+        let (m, n, p, q) = (config.m as u32, config.n as u32, config.p as u32, config.q as u32);
+        assert!((PLL_M_MIN <= m) && (m <= PLL_M_MAX));
+        assert!((PLL_N_MIN <= n) && (n <= PLL_N_MAX));
+        assert!((p == 2) || (p == 4) || (p == 6) || (p == 8));
+        assert!((PLL_Q_MIN <= q) && (q <= PLL_Q_MAX));
+        assert!((PLL_VCO_INPUT_MIN * m <= input) && (input <= PLL_VCO_INPUT_MAX * m));
+        let vco = ((input as u64) * (n as u64) / (m as u64)) as u32;
+        assert!((PLL_VCO_OUTPUT_MIN <= vco) && (vco <= PLL_VCO_OUTPUT_MAX));
+        assert_eq!(config.sysclk, vco / p);
+        assert_eq!(config.usbclk, vco / q);
+        assert!(config.sysclk <= SYSCLK_MAX);
+        assert!(config.usbclk <= USB_CLOCK);
+    }
+
+    #[test]
+    fn pll_exact_targets_with_usb() {
+        let config = compute_pll(8_000_000, 120_000_000, true).unwrap();
+        assert_eq!(config, PLLConfig { m: 7, n: 210, p: 2, q: 5, sysclk: 120_000_000, usbclk: 48_000_000 });
+        check_ranges(8_000_000, &config);
+
+        let config = compute_pll(25_000_000, 120_000_000, true).unwrap();
+        assert_eq!((config.sysclk, config.usbclk), (120_000_000, 48_000_000));
+        check_ranges(25_000_000, &config);
+
+        let config = compute_pll(8_000_000, 72_000_000, true).unwrap();
+        assert_eq!((config.sysclk, config.usbclk), (72_000_000, 48_000_000));
+        check_ranges(8_000_000, &config);
+    }
+
+    #[test]
+    fn pll_without_usb_keeps_q_output_under_48mhz() {
+        let config = compute_pll(16_000_000, 100_000_000, false).unwrap();
+        assert_eq!(config.sysclk, 100_000_000);
+        assert_eq!(config.q, 9);
+        check_ranges(16_000_000, &config);
+    }
+
+    #[test]
+    fn pll_prefers_the_highest_vco_input() {
+        // 8MHz / 4 and 8MHz / 8 both reach 96MHz, 2MHz gives less jitter
+        let config = compute_pll(8_000_000, 96_000_000, false).unwrap();
+        assert_eq!(config.m, 4);
+        check_ranges(8_000_000, &config);
+    }
+
+    #[test]
+    fn pll_ranges_hold_for_every_input() {
+        for input in [4_000_000, 8_000_000, 12_000_000, 16_000_000, 25_000_000, 26_000_000].iter() {
+            for sysclk in [24_000_000, 48_000_000, 60_000_000, 96_000_000, 120_000_000].iter() {
+                for usb48 in [false, true].iter() {
+                    if let Ok(config) = compute_pll(*input, *sysclk, *usb48) {
+                        check_ranges(*input, &config);
+                        if *usb48 {
+                            assert_eq!(config.usbclk, USB_CLOCK);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pll_closest_below_the_minimum_output() {
+        // the slowest output is 192MHz / 8
+        let config = compute_pll(12_000_000, 1_000_000, false).unwrap();
+        assert_eq!(config.sysclk, 24_000_000);
+    }
+
+    #[test]
+    fn pll_unreachable_targets() {
+        assert!(compute_pll(8_000_000, 0, false).is_err());
+        assert!(compute_pll(8_000_000, 130_000_000, false).is_err());
+        // no M brings the VCO input into [1; 2]MHz
+        assert!(compute_pll(500_000, 120_000_000, false).is_err());
+        assert!(compute_pll(130_000_000, 120_000_000, false).is_err());
+        // no N gives an exact multiple of 48MHz
+        assert!(compute_pll(3_999_997, 120_000_000, true).is_err());
+        assert!(compute_pll(3_999_997, 120_000_000, false).is_ok());
+    }
+}
@@ -0,0 +1,175 @@
+//! Several devices sharing one SPI bus, each with its own chip select and
+//! settings.
+//!
+//! A device only talks on the bus inside `SpiDevice::transaction`, which
+//! takes the bus, applies the device settings, asserts its chip select around
+//! the closure and releases everything afterwards. The bus is taken with
+//! interrupts masked, so a transaction attempted from an ISR while another one
+//! is in flight fails with an error instead of corrupting it. While a
+//! transaction holds the bus, the SPI reports itself busy to any other
+//! context, so an ISR cannot bypass it by using the SPI directly.
+
+use collections::string::String;
+use collections::string::ToString;
+
+use silica::peripheral::gpio::Output as IOutput;
+
+use Peripheral;
+use critical_section;
+use active_exception;
+use gpio::{Mode, Out, PinPeripheral};
+use super::*;
+
+/// A master SPI bus shared by several `SpiDevice`s.
+pub struct SpiBus<'a> {
+    spi: &'a SPIPeripheral<'a>
+}
+unsafe impl<'a> Sync for SpiBus<'a> {}
+
+impl<'a> SpiBus<'a> {
+    /// The SPI must only be used through the bus afterwards.
+    pub const fn new(spi: &'a SPIPeripheral<'a>) -> SpiBus<'a> {
+        SpiBus {
+            spi: spi
+        }
+    }
+
+    fn id(&self) -> Result<usize, String> {
+        match self.spi.context_id() {
+            Some(id) => Ok(id),
+            None => Err("Invalid SPI interrupt".to_string())
+        }
+    }
+
+    /// Takes the bus if it is free.
+    fn acquire(&self) -> Result<(), String> {
+        let id = match self.id() {
+            Ok(id) => id,
+            Err(msg) => return Err(msg)
+        };
+        let taken = critical_section(|| unsafe {
+            if BUS_OWNERS[id].is_some() {
+                return false;
+            }
+            BUS_OWNERS[id] = Some(active_exception());
+            true
+        });
+        if !taken {
+            return Err("The SPI bus is busy".to_string());
+        }
+        Ok(())
+    }
+
+    fn release(&self) {
+        if let Ok(id) = self.id() {
+            critical_section(|| unsafe {
+                BUS_OWNERS[id] = None;
+            });
+        }
+    }
+
+    pub fn is_taken(&self) -> bool {
+        match self.id() {
+            Ok(id) => critical_section(|| unsafe { BUS_OWNERS[id].is_some() }),
+            Err(_) => false
+        }
+    }
+
+    /// Applies `config` unless the bus already runs with it.
+    fn apply(&self, config: &Config) -> Result<(), String> {
+        let (cr1, cr2, _) = match self.spi.compute_config(Role::Master, config) {
+            Ok(res) => res,
+            Err(msg) => return Err(msg)
+        };
+        let spi = self.spi.registers();
+        let current = spi.control1.read();
+        if ((current & CR1_SPE) == CR1_SPE) && ((current & !CR1_SPE) == cr1) &&
+           ((spi.control2.read() & CR2_SSOE) == cr2) {
+            return Ok(());
+        }
+        spi.control1.update(0, CR1_SPE);
+        spi.control2.update(cr2, CR2_SSOE);
+        spi.control1.write(cr1 | CR1_SPE);
+        Ok(())
+    }
+}
+
+impl<'a> Peripheral for SpiBus<'a> {
+    fn init(&self) -> Result<(), String> {
+        if self.spi.pin_nss.is_some() {
+            return Err("A shared bus uses the devices chip selects, not NSS".to_string());
+        }
+        self.spi.init()
+    }
+
+    fn deinit(&self) -> Result<(), String> {
+        if let Err(msg) = self.acquire() {
+            return Err(msg);
+        }
+        let res = self.spi.deinit();
+        self.release();
+        res
+    }
+}
+
+/// A device on a shared bus, selected by driving `chip_select` low.
+pub struct SpiDevice<'a> {
+    pub bus: &'a SpiBus<'a>,
+    /// Push-pull or open-drain output, initially high.
+    pub chip_select: &'a PinPeripheral<'a>,
+    pub config: Config
+}
+unsafe impl<'a> Sync for SpiDevice<'a> {}
+
+impl<'a> SpiDevice<'a> {
+    fn select(&self, selected: bool) {
+        Out::from(self.chip_select).write(!selected);
+    }
+
+    /// Runs `f` with the bus set up for this device and its chip select
+    /// asserted. Chip select is released once the last frame is out, even
+    /// when `f` started an interrupt or DMA transfer. Fails without calling
+    /// `f` if the bus is in use. An interrupt transfer that stalls for
+    /// `FRAME_TIMEOUT` is aborted and the bus released with an error.
+    pub fn transaction<R, F: FnOnce(&SPIPeripheral) -> Result<R, String>>(&self, f: F) -> Result<R, String> {
+        if let Err(msg) = self.bus.acquire() {
+            return Err(msg);
+        }
+        if let Err(msg) = self.bus.apply(&self.config) {
+            self.bus.release();
+            return Err(msg);
+        }
+
+        self.select(true);
+        let res = f(self.bus.spi);
+        let done = self.bus.spi.wait_transfer();
+        self.select(false);
+
+        self.bus.release();
+        if res.is_ok() {
+            if let Err(msg) = done {
+                return Err(msg);
+            }
+        }
+        res
+    }
+}
+
+impl<'a> Peripheral for SpiDevice<'a> {
+    fn init(&self) -> Result<(), String> {
+        match self.chip_select.mode {
+            Mode::Out(_, _) => {},
+            _ => return Err("The chip select pin must be an output".to_string())
+        }
+        if let Err(msg) = self.bus.spi.compute_config(Role::Master, &self.config) {
+            return Err(msg);
+        }
+        init_peripheral![Some(self.chip_select)];
+        self.select(false);
+        Ok(())
+    }
+
+    fn deinit(&self) -> Result<(), String> {
+        self.chip_select.deinit()
+    }
+}
@@ -0,0 +1,34 @@
+pub const CR1_CPHA: u16 = 0x0001;
+pub const CR1_CPOL: u16 = 0x0002;
+pub const CR1_MSTR: u16 = 0x0004;
+pub const CR1_BR_MASK: u16 = 0x0038;
+pub const CR1_BR_SHIFT: u16 = 3;
+pub const CR1_SPE: u16 = 0x0040;
+pub const CR1_LSBFIRST: u16 = 0x0080;
+pub const CR1_SSI: u16 = 0x0100;
+pub const CR1_SSM: u16 = 0x0200;
+pub const CR1_RXONLY: u16 = 0x0400;
+pub const CR1_DFF: u16 = 0x0800;
+pub const CR1_CRCNEXT: u16 = 0x1000;
+pub const CR1_CRCEN: u16 = 0x2000;
+pub const CR1_BIDIOE: u16 = 0x4000;
+pub const CR1_BIDIMODE: u16 = 0x8000;
+
+pub const CR2_RXDMAEN: u16 = 0x0001;
+pub const CR2_TXDMAEN: u16 = 0x0002;
+pub const CR2_SSOE: u16 = 0x0004;
+pub const CR2_FRF: u16 = 0x0010;
+pub const CR2_ERRIE: u16 = 0x0020;
+pub const CR2_RXNEIE: u16 = 0x0040;
+pub const CR2_TXEIE: u16 = 0x0080;
+
+pub const SR_RXNE: u16 = 0x0001;
+pub const SR_TXE: u16 = 0x0002;
+pub const SR_CHSIDE: u16 = 0x0004;
+pub const SR_UDR: u16 = 0x0008;
+pub const SR_CRCERR: u16 = 0x0010;
+pub const SR_MODF: u16 = 0x0020;
+pub const SR_OVR: u16 = 0x0040;
+pub const SR_BSY: u16 = 0x0080;
+pub const SR_FRE: u16 = 0x0100;
+pub const SR_ERRORS: u16 = SR_UDR | SR_CRCERR | SR_MODF | SR_OVR | SR_FRE;
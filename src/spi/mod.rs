@@ -0,0 +1,831 @@
+use collections::string::String;
+use collections::string::ToString;
+
+use rcc;
+use IRQType;
+use Peripheral;
+use critical_section;
+use active_exception;
+use interrupts_masked;
+use registers::*;
+use time;
+use dma::{self, DMAStreamPeripheral, Transfer, Direction, DataSize, Priority};
+use gpio::PinPeripheral;
+use gpio::af::{self, Signal};
+
+mod flags;
+/// Bus sharing between devices
+pub mod bus;
+
+pub use self::flags::*;
+
+#[repr(C)]
+pub struct SPIRegisters {
+    control1: Rw<u16>,
+    reserved0: u16,
+    control2: Rw<u16>,
+    reserved1: u16,
+    status: Rw<u16>,
+    reserved2: u16,
+    data: Rw<u16>,
+    reserved3: u16,
+    crc_polynomial: Rw<u16>,
+    reserved4: u16,
+    rx_crc: Ro<u16>,
+    reserved5: u16,
+    tx_crc: Ro<u16>,
+    reserved6: u16,
+    i2s_config: Rw<u16>,
+    reserved7: u16,
+    i2s_prescaler: Rw<u16>,
+    reserved8: u16
+}
+
+/// Clock polarity and phase, the value is CPOL << 1 | CPHA.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Mode {
+    /// Idle low, sample on the rising edge.
+    Mode0 = 0,
+    /// Idle low, sample on the falling edge.
+    Mode1 = 1,
+    /// Idle high, sample on the falling edge.
+    Mode2 = 2,
+    /// Idle high, sample on the rising edge.
+    Mode3 = 3
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum FrameSize {
+    Bits8,
+    Bits16
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum BitOrder {
+    MsbFirst,
+    LsbFirst
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Role {
+    Master,
+    /// Only `transfer_dma` is available in slave mode.
+    Slave
+}
+
+/// Bus settings of a device.
+#[derive(Copy, Clone, PartialEq)]
+pub struct Config {
+    pub mode: Mode,
+    pub frame_size: FrameSize,
+    pub bit_order: BitOrder,
+    /// Maximum SCK frequency in Hz, ignored in slave mode.
+    pub frequency: u32
+}
+
+/// Returns the BR field giving the fastest SCK not above `frequency` from
+/// `pclk`, and the resulting frequency.
+pub fn compute_prescaler(pclk: usize, frequency: u32) -> Result<(u16, u32), &'static str> {
+    if frequency == 0 {
+        return Err("The SPI frequency must not be null.");
+    }
+    for br in 0..8 {
+        let sck = pclk / (2 << br);
+        if sck <= (frequency as usize) {
+            return Ok((br as u16, sck as u32));
+        }
+    }
+    Err("The SPI frequency is too low for this bus clock.")
+}
+
+/// Milliseconds the transfers wait for each frame. In slave mode, where only
+/// DMA transfers are allowed, the master must clock every frame within it.
+const FRAME_TIMEOUT: u32 = 100;
+
+/// Called from the ISR once an interrupt transfer ends, with the registered
+/// argument and the SR error flags (0 on success).
+pub type TransferCallback = fn(usize, u16);
+
+/// State of an interrupt-driven transfer. Frames are sent one at a time: the
+/// next one is written when the previous one has been received, so the
+/// receiver can never overrun.
+struct Context {
+    registers: *mut SPIRegisters,
+    tx: *const u8,
+    rx: *mut u8,
+    /// Length in frames.
+    len: usize,
+    received: usize,
+    frame16: bool,
+    busy: bool,
+    callback: Option<TransferCallback>,
+    argument: usize
+}
+impl Context {
+    const fn new() -> Context {
+        Context {
+            registers: 0 as *mut SPIRegisters,
+            tx: 0 as *const u8,
+            rx: 0 as *mut u8,
+            len: 0,
+            received: 0,
+            frame16: false,
+            busy: false,
+            callback: None,
+            argument: 0
+        }
+    }
+
+    /// Frame `index` to send, all ones when there is nothing to send.
+    unsafe fn frame(&self, index: usize) -> u16 {
+        if self.tx.is_null() {
+            0xFFFF
+        } else if self.frame16 {
+            (*self.tx.offset((2 * index) as isize) as u16) |
+                ((*self.tx.offset((2 * index + 1) as isize) as u16) << 8)
+        } else {
+            *self.tx.offset(index as isize) as u16
+        }
+    }
+
+    unsafe fn store(&mut self, index: usize, frame: u16) {
+        if self.rx.is_null() {
+            return;
+        }
+        if self.frame16 {
+            *self.rx.offset((2 * index) as isize) = frame as u8;
+            *self.rx.offset((2 * index + 1) as isize) = (frame >> 8) as u8;
+        } else {
+            *self.rx.offset(index as isize) = frame as u8;
+        }
+    }
+
+    unsafe fn finish(&mut self, errors: u16) {
+        (*self.registers).control2.update(0, CR2_RXNEIE | CR2_ERRIE);
+        self.busy = false;
+        if let Some(callback) = self.callback {
+            callback(self.argument, errors);
+        }
+    }
+}
+
+static mut CONTEXTS: [Context; 3] = [
+    Context::new(), Context::new(), Context::new()
+];
+
+// exception running the bus transaction that holds each SPI, 0 for thread
+// mode, indexed like the contexts
+static mut BUS_OWNERS: [Option<u32>; 3] = [None, None, None];
+
+unsafe fn on_interrupt(id: usize) {
+    let ctx = &mut CONTEXTS[id];
+    if ctx.registers.is_null() || !ctx.busy {
+        return;
+    }
+    let spi = &mut *ctx.registers;
+    let sr = spi.status.read();
+
+    if (sr & SR_ERRORS) != 0 {
+        // reading DR then SR clears OVR
+        spi.data.read();
+        spi.status.read();
+        ctx.finish(sr & SR_ERRORS);
+        return;
+    }
+    if (sr & SR_RXNE) == SR_RXNE {
+        let frame = spi.data.read();
+        let index = ctx.received;
+        ctx.store(index, frame);
+        ctx.received += 1;
+        if ctx.received == ctx.len {
+            ctx.finish(0);
+        } else {
+            spi.data.write(ctx.frame(ctx.received));
+        }
+    }
+}
+
+pub unsafe extern "C" fn spi1_handler() {
+    on_interrupt(0);
+}
+pub unsafe extern "C" fn spi2_handler() {
+    on_interrupt(1);
+}
+pub unsafe extern "C" fn spi3_handler() {
+    on_interrupt(2);
+}
+
+pub struct SPIPeripheral<'a> {
+    pub base_address: *mut SPIRegisters,
+    pub clock: rcc::RCCPeripheral,
+    pub isr_id: IRQType,
+    pub dma_rx: Option<&'a DMAStreamPeripheral<'a>>,
+    pub dma_tx: Option<&'a DMAStreamPeripheral<'a>>,
+
+    pub pin_sck: Option<&'a PinPeripheral<'a>>,
+    pub pin_miso: Option<&'a PinPeripheral<'a>>,
+    pub pin_mosi: Option<&'a PinPeripheral<'a>>,
+    /// Hardware slave select, output in master mode and input in slave mode.
+    pub pin_nss: Option<&'a PinPeripheral<'a>>
+}
+unsafe impl<'a> Sync for SPIPeripheral<'a> {}
+
+impl<'a> SPIPeripheral<'a> {
+    /// Index of the interrupt context used by this SPI.
+    fn context_id(&self) -> Option<usize> {
+        match self.isr_id {
+            IRQType::SPI1 => Some(0),
+            IRQType::SPI2 => Some(1),
+            IRQType::SPI3 => Some(2),
+            _ => None
+        }
+    }
+
+    /// SCK, MISO, MOSI and NSS signals of this SPI.
+    fn signals(&self) -> Option<[Signal; 4]> {
+        match self.clock.clock {
+            rcc::Clock::SPI1 => Some([Signal::SPI1_SCK, Signal::SPI1_MISO, Signal::SPI1_MOSI, Signal::SPI1_NSS]),
+            rcc::Clock::SPI2 => Some([Signal::SPI2_SCK, Signal::SPI2_MISO, Signal::SPI2_MOSI, Signal::SPI2_NSS]),
+            rcc::Clock::SPI3 => Some([Signal::SPI3_SCK, Signal::SPI3_MISO, Signal::SPI3_MOSI, Signal::SPI3_NSS]),
+            _ => None
+        }
+    }
+
+    /// Checks each pin can carry its signal.
+    fn check_pins(&self) -> Result<(), String> {
+        let signals = match self.signals() {
+            Some(signals) => signals,
+            None => return Err("Invalid SPI clock".to_string())
+        };
+        let pins = [self.pin_sck, self.pin_miso, self.pin_mosi, self.pin_nss];
+        for i in 0..4 {
+            if let Some(pin) = pins[i] {
+                if let Err(msg) = af::check_pin(pin, signals[i]) {
+                    return Err(msg);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn registers(&self) -> &mut SPIRegisters {
+        unsafe { &mut *self.base_address }
+    }
+
+    fn is_frame16(&self) -> bool {
+        (self.registers().control1.read() & CR1_DFF) == CR1_DFF
+    }
+
+    /// Whether a frame is being shifted, an interrupt transfer is running or
+    /// a bus transaction of another context holds the SPI.
+    pub fn is_busy(&self) -> bool {
+        let running = match self.context_id() {
+            Some(id) => critical_section(|| unsafe {
+                let locked = match BUS_OWNERS[id] {
+                    Some(owner) => owner != active_exception(),
+                    None => false
+                };
+                CONTEXTS[id].busy || locked
+            }),
+            None => false
+        };
+        running || ((self.registers().status.read() & SR_BSY) == SR_BSY)
+    }
+
+    /// Frames are loaded one at a time by the CPU, which a master clocking
+    /// back-to-back frames would outrun, so slave mode is DMA-only.
+    fn check_master(&self) -> Result<(), String> {
+        if (self.registers().control1.read() & CR1_MSTR) != CR1_MSTR {
+            return Err("Slave mode transfers need DMA".to_string());
+        }
+        Ok(())
+    }
+
+    /// Waits for the last frame to be shifted out.
+    fn wait_idle(&self) -> Result<(), String> {
+        let spi = self.registers();
+        let idle = time::wait_for(FRAME_TIMEOUT, || (spi.status.read() & SR_TXE) == SR_TXE) &&
+            time::wait_for(FRAME_TIMEOUT, || (spi.status.read() & SR_BSY) != SR_BSY);
+        if !idle {
+            return Err("SPI transfer timeout".to_string());
+        }
+        Ok(())
+    }
+
+    /// Waits for the running interrupt transfer, if any, then for the last
+    /// frame. Gives up once no frame has been received for `FRAME_TIMEOUT`
+    /// and aborts the transfer without calling its callback.
+    fn wait_transfer(&self) -> Result<(), String> {
+        if let Some(id) = self.context_id() {
+            let state = || critical_section(|| unsafe { (CONTEXTS[id].busy, CONTEXTS[id].received) });
+            loop {
+                let (busy, received) = state();
+                if !busy {
+                    break;
+                }
+                if !time::wait_for(FRAME_TIMEOUT, || state() != (true, received)) {
+                    self.abort(id);
+                    return Err("SPI transfer timeout".to_string());
+                }
+            }
+        }
+        self.wait_idle()
+    }
+
+    /// Stops the interrupt transfer of context `id`.
+    fn abort(&self, id: usize) {
+        self.isr_id.disable();
+        self.registers().control2.update(0, CR2_RXNEIE | CR2_ERRIE | CR2_TXEIE);
+        critical_section(|| unsafe {
+            CONTEXTS[id] = Context::new();
+        });
+    }
+
+    /// Returns the CR1 and CR2 values for `role` and `config`, and the SCK
+    /// frequency in master mode.
+    pub fn compute_config(&self, role: Role, config: &Config) -> Result<(u16, u16, u32), String> {
+        let mut cr1 = config.mode as u16;
+        let mut cr2 = 0;
+        let mut frequency = 0;
+        if config.frame_size == FrameSize::Bits16 {
+            cr1 |= CR1_DFF;
+        }
+        if config.bit_order == BitOrder::LsbFirst {
+            cr1 |= CR1_LSBFIRST;
+        }
+        match role {
+            Role::Master => {
+                let (br, sck) = match compute_prescaler(self.clock.get_clock(), config.frequency) {
+                    Ok(res) => res,
+                    Err(msg) => return Err(msg.to_string())
+                };
+                cr1 |= CR1_MSTR | (br << CR1_BR_SHIFT);
+                frequency = sck;
+                if self.pin_nss.is_some() {
+                    // NSS driven low while the SPI is enabled
+                    cr2 |= CR2_SSOE;
+                } else {
+                    cr1 |= CR1_SSM | CR1_SSI;
+                }
+            },
+            Role::Slave => {
+                if self.pin_nss.is_none() {
+                    // always selected
+                    cr1 |= CR1_SSM;
+                }
+            }
+        }
+        Ok((cr1, cr2, frequency))
+    }
+
+    /// Applies `config` and enables the SPI. Returns the actual SCK frequency
+    /// in master mode, 0 in slave mode.
+    pub fn configure(&self, role: Role, config: &Config) -> Result<u32, String> {
+        if self.is_busy() {
+            return Err("The SPI is busy".to_string());
+        }
+        let (cr1, cr2, frequency) = match self.compute_config(role, config) {
+            Ok(res) => res,
+            Err(msg) => return Err(msg)
+        };
+        let spi = self.registers();
+        spi.control1.update(0, CR1_SPE);
+        spi.control2.write(cr2);
+        spi.control1.write(cr1);
+        spi.control1.update(CR1_SPE, CR1_SPE);
+        Ok(frequency)
+    }
+
+    /// Exchanges one frame, waiting at most `FRAME_TIMEOUT` for each step.
+    fn exchange(&self, frame: u16) -> Result<u16, String> {
+        let spi = self.registers();
+        if !time::wait_for(FRAME_TIMEOUT, || (spi.status.read() & SR_TXE) == SR_TXE) {
+            return Err("SPI transfer timeout".to_string());
+        }
+        spi.data.write(frame);
+        if !time::wait_for(FRAME_TIMEOUT, || (spi.status.read() & (SR_RXNE | SR_ERRORS)) != 0) {
+            return Err("SPI transfer timeout".to_string());
+        }
+        let sr = spi.status.read();
+        if (sr & SR_ERRORS) != 0 {
+            spi.data.read();
+            spi.status.read();
+            return Err("SPI transfer error".to_string());
+        }
+        Ok(spi.data.read())
+    }
+
+    /// Sends `tx` while filling `rx`, busy-waiting. In 16-bit mode frames are
+    /// little-endian byte pairs. Fails if a frame is not exchanged within
+    /// `FRAME_TIMEOUT`. Master mode only.
+    pub fn transfer(&self, tx: &[u8], rx: &mut [u8]) -> Result<(), String> {
+        if tx.len() != rx.len() {
+            return Err("Transmit and receive lengths differ".to_string());
+        }
+        if self.is_busy() {
+            return Err("The SPI is busy".to_string());
+        }
+        if let Err(msg) = self.check_master() {
+            return Err(msg);
+        }
+        if self.is_frame16() {
+            if (tx.len() % 2) != 0 {
+                return Err("16-bit transfers need an even length".to_string());
+            }
+            for i in 0..(tx.len() / 2) {
+                let frame = (tx[2 * i] as u16) | ((tx[2 * i + 1] as u16) << 8);
+                match self.exchange(frame) {
+                    Ok(frame) => {
+                        rx[2 * i] = frame as u8;
+                        rx[2 * i + 1] = (frame >> 8) as u8;
+                    },
+                    Err(msg) => return Err(msg)
+                }
+            }
+        } else {
+            for i in 0..tx.len() {
+                match self.exchange(tx[i] as u16) {
+                    Ok(frame) => rx[i] = frame as u8,
+                    Err(msg) => return Err(msg)
+                }
+            }
+        }
+        self.wait_idle()
+    }
+
+    /// Sends `tx`, dropping what is received. Master mode only.
+    pub fn write(&self, tx: &[u8]) -> Result<(), String> {
+        let frame16 = self.is_frame16();
+        let step = if frame16 { 2 } else { 1 };
+        if (tx.len() % step) != 0 {
+            return Err("16-bit transfers need an even length".to_string());
+        }
+        if self.is_busy() {
+            return Err("The SPI is busy".to_string());
+        }
+        if let Err(msg) = self.check_master() {
+            return Err(msg);
+        }
+        let mut i = 0;
+        while i < tx.len() {
+            let frame = if frame16 { (tx[i] as u16) | ((tx[i + 1] as u16) << 8) } else { tx[i] as u16 };
+            if let Err(msg) = self.exchange(frame) {
+                return Err(msg);
+            }
+            i += step;
+        }
+        self.wait_idle()
+    }
+
+    /// Fills `rx`, sending all ones. Master mode only.
+    pub fn read(&self, rx: &mut [u8]) -> Result<(), String> {
+        let frame16 = self.is_frame16();
+        let step = if frame16 { 2 } else { 1 };
+        if (rx.len() % step) != 0 {
+            return Err("16-bit transfers need an even length".to_string());
+        }
+        if self.is_busy() {
+            return Err("The SPI is busy".to_string());
+        }
+        if let Err(msg) = self.check_master() {
+            return Err(msg);
+        }
+        let mut i = 0;
+        while i < rx.len() {
+            match self.exchange(0xFFFF) {
+                Ok(frame) => {
+                    rx[i] = frame as u8;
+                    if frame16 {
+                        rx[i + 1] = (frame >> 8) as u8;
+                    }
+                },
+                Err(msg) => return Err(msg)
+            }
+            i += step;
+        }
+        self.wait_idle()
+    }
+
+    /// Starts an interrupt-driven transfer of `len` bytes and returns. Without
+    /// `tx` all ones are sent, without `rx` the received data is dropped.
+    /// `callback` is called from the ISR when it ends. Master mode only.
+    ///
+    /// Inside a bus transaction this needs thread mode with interrupts
+    /// enabled: the transaction waits for the transfer to end before
+    /// releasing chip select, which would never happen if the SPI interrupt
+    /// could not preempt it.
+    pub fn start_transfer(&self, tx: Option<&'static [u8]>, rx: Option<&'static mut [u8]>, len: usize,
+                          callback: TransferCallback, argument: usize) -> Result<(), String> {
+        let id = match self.context_id() {
+            Some(id) => id,
+            None => return Err("Invalid SPI interrupt".to_string())
+        };
+        let frame16 = self.is_frame16();
+        let frames = if frame16 { len / 2 } else { len };
+        if (frames == 0) || (frame16 && ((len % 2) != 0)) {
+            return Err("Invalid transfer length".to_string());
+        }
+        let tx_ptr = match tx {
+            Some(tx) if tx.len() < len => return Err("The transmit buffer is too short".to_string()),
+            Some(tx) => tx.as_ptr(),
+            None => 0 as *const u8
+        };
+        let rx_ptr = match rx {
+            Some(ref rx) if rx.len() < len => return Err("The receive buffer is too short".to_string()),
+            Some(rx) => rx.as_mut_ptr(),
+            None => 0 as *mut u8
+        };
+        if let Err(msg) = self.check_master() {
+            return Err(msg);
+        }
+
+        // the caller's state, read before the critical section masks interrupts
+        let masked = interrupts_masked();
+        let claimed = critical_section(|| unsafe {
+            if self.is_busy() {
+                return Err("The SPI is busy".to_string());
+            }
+            if BUS_OWNERS[id].is_some() && ((active_exception() != 0) || masked) {
+                return Err("Interrupt transfers in a transaction need thread mode with interrupts enabled".to_string());
+            }
+            CONTEXTS[id] = Context {
+                registers: self.base_address,
+                tx: tx_ptr,
+                rx: rx_ptr,
+                len: frames,
+                received: 0,
+                frame16: frame16,
+                busy: true,
+                callback: Some(callback),
+                argument: argument
+            };
+            Ok(())
+        });
+        if let Err(msg) = claimed {
+            return Err(msg);
+        }
+
+        let spi = self.registers();
+        // drop any stale frame
+        spi.data.read();
+        spi.status.read();
+
+        self.isr_id.disable();
+        let first = unsafe { CONTEXTS[id].frame(0) };
+        spi.control2.update(CR2_RXNEIE | CR2_ERRIE, CR2_RXNEIE | CR2_ERRIE | CR2_TXEIE);
+        spi.data.write(first);
+        self.isr_id.enable();
+        Ok(())
+    }
+
+    /// Full-duplex transfer through the DMA streams, waits for completion. In
+    /// 16-bit mode both buffers must be halfword aligned. In slave mode this
+    /// waits for the master to clock every frame.
+    pub fn transfer_dma(&self, tx: &[u8], rx: &mut [u8]) -> Result<(), String> {
+        let (dma_rx, dma_tx) = match (self.dma_rx, self.dma_tx) {
+            (Some(dma_rx), Some(dma_tx)) => (dma_rx, dma_tx),
+            _ => return Err("DMA transfers need both DMA streams".to_string())
+        };
+        if tx.len() != rx.len() {
+            return Err("Transmit and receive lengths differ".to_string());
+        }
+        let frame16 = self.is_frame16();
+        let (size, frames) = if frame16 { (DataSize::HalfWord, tx.len() / 2) } else { (DataSize::Byte, tx.len()) };
+        if (frames == 0) || (frame16 && ((tx.len() % 2) != 0)) {
+            return Err("Invalid transfer length".to_string());
+        }
+        let count = match dma::transfer_count(frames) {
+            Ok(count) => count,
+            Err(msg) => return Err(msg)
+        };
+        if frame16 && ((((tx.as_ptr() as usize) | (rx.as_ptr() as usize)) & 1) != 0) {
+            return Err("16-bit DMA transfers need halfword aligned buffers".to_string());
+        }
+        if self.is_busy() {
+            return Err("The SPI is busy".to_string());
+        }
+
+        let spi = self.registers();
+        let data = &spi.data as *const Rw<u16> as usize;
+        spi.data.read();
+        spi.status.read();
+
+        let res = dma_rx.start(&Transfer {
+            direction: Direction::PeripheralToMemory,
+            peripheral: data,
+            peripheral_size: size,
+            peripheral_increment: false,
+            memory: rx.as_mut_ptr() as usize,
+            memory_size: size,
+            memory_increment: true,
+            count: count,
+            circular: false,
+            priority: Priority::High,
+            interrupts: false
+        });
+        if let Err(msg) = res {
+            return Err(msg);
+        }
+        let res = dma_tx.start(&Transfer {
+            direction: Direction::MemoryToPeripheral,
+            peripheral: data,
+            peripheral_size: size,
+            peripheral_increment: false,
+            memory: tx.as_ptr() as usize,
+            memory_size: size,
+            memory_increment: true,
+            count: count,
+            circular: false,
+            priority: Priority::Medium,
+            interrupts: false
+        });
+        if let Err(msg) = res {
+            let _ = dma_rx.stop();
+            return Err(msg);
+        }
+        // RX first so that no frame is missed
+        spi.control2.update(CR2_RXDMAEN, CR2_RXDMAEN);
+        spi.control2.update(CR2_TXDMAEN, CR2_TXDMAEN);
+
+        let res = match dma_tx.wait(FRAME_TIMEOUT) {
+            Ok(_) => dma_rx.wait(FRAME_TIMEOUT),
+            Err(msg) => Err(msg)
+        };
+        if res.is_err() {
+            let _ = dma_tx.stop();
+            let _ = dma_rx.stop();
+        }
+        spi.control2.update(0, CR2_RXDMAEN | CR2_TXDMAEN);
+        let idle = self.wait_idle();
+        if res.is_err() {
+            return res;
+        }
+        idle
+    }
+}
+
+impl<'a> Peripheral for SPIPeripheral<'a> {
+    fn init(&self) -> Result<(), String> {
+        if self.context_id().is_none() {
+            return Err("Invalid SPI interrupt".to_string());
+        }
+        if let Err(msg) = self.check_pins() {
+            return Err(msg);
+        }
+        init_peripheral![self.pin_sck, self.pin_miso, self.pin_mosi, self.pin_nss];
+        init_peripheral![Some(&self.clock)];
+        init_peripheral![self.dma_rx, self.dma_tx];
+
+        let spi = self.registers();
+        spi.control1.write(0);
+        spi.control2.write(0);
+        Ok(())
+    }
+
+    fn deinit(&self) -> Result<(), String> {
+        if let Some(id) = self.context_id() {
+            self.isr_id.disable();
+            unsafe {
+                CONTEXTS[id] = Context::new();
+            }
+        }
+        self.registers().control1.update(0, CR1_SPE);
+        self.clock.deinit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::slice;
+    use dma::{self, DMAPeripheral, DMARegisters, DMAStreamRegisters};
+
+    // CR1 and SR in the fake SPI block, registers are 32 bits apart
+    const CR1: usize = 0;
+    const SR: usize = 4;
+
+    fn spi<'a>(regs: &mut [u16; 18], rcc: &mut [u32; 36], isr_id: IRQType, clock: rcc::Clock,
+               dma_rx: Option<&'a DMAStreamPeripheral<'a>>,
+               dma_tx: Option<&'a DMAStreamPeripheral<'a>>) -> SPIPeripheral<'a> {
+        SPIPeripheral {
+            base_address: regs.as_mut_ptr() as *mut SPIRegisters,
+            clock: rcc::RCCPeripheral {
+                rcc: rcc.as_mut_ptr() as *mut rcc::RCCRegisters,
+                clock: clock
+            },
+            isr_id: isr_id,
+            dma_rx: dma_rx,
+            dma_tx: dma_tx,
+            pin_sck: None,
+            pin_miso: None,
+            pin_mosi: None,
+            pin_nss: None
+        }
+    }
+
+    #[test]
+    fn compute_prescaler_picks_the_fastest_allowed() {
+        assert_eq!(compute_prescaler(60_000_000, 30_000_000), Ok((0, 30_000_000)));
+        assert_eq!(compute_prescaler(60_000_000, 10_000_000), Ok((2, 7_500_000)));
+        assert!(compute_prescaler(60_000_000, 100_000).is_err());
+        assert!(compute_prescaler(60_000_000, 0).is_err());
+    }
+
+    #[test]
+    fn blocking_transfers_refuse_a_busy_spi() {
+        let mut regs = [0; 18];
+        let mut rcc = [0; 36];
+        regs[CR1] = CR1_MSTR;
+        let spi = spi(&mut regs, &mut rcc, IRQType::SPI3, rcc::Clock::SPI3, None, None);
+        unsafe {
+            CONTEXTS[2].busy = true;
+        }
+        let mut rx = [0; 2];
+        assert_eq!(spi.transfer(&[1, 2], &mut rx), Err("The SPI is busy".to_string()));
+        assert_eq!(spi.write(&[1, 2]), Err("The SPI is busy".to_string()));
+        assert_eq!(spi.read(&mut rx), Err("The SPI is busy".to_string()));
+        unsafe {
+            CONTEXTS[2].busy = false;
+        }
+    }
+
+    fn ignore_end(_: usize, _: u16) {}
+
+    #[test]
+    fn slave_mode_refuses_cpu_transfers() {
+        let mut regs = [0; 18];
+        let mut rcc = [0; 36];
+        let spi = spi(&mut regs, &mut rcc, IRQType::SPI1, rcc::Clock::SPI1, None, None);
+        let slave = Err("Slave mode transfers need DMA".to_string());
+        let mut rx = [0; 2];
+        assert_eq!(spi.transfer(&[1, 2], &mut rx), slave);
+        assert_eq!(spi.write(&[1, 2]), slave);
+        assert_eq!(spi.read(&mut rx), slave);
+        assert_eq!(spi.start_transfer(None, None, 2, ignore_end, 0), slave);
+        assert!(!spi.is_busy());
+    }
+
+    #[test]
+    fn wait_transfer_returns_once_idle() {
+        let mut regs = [0; 18];
+        let mut rcc = [0; 36];
+        let spi = spi(&mut regs, &mut rcc, IRQType::SPI1, rcc::Clock::SPI1, None, None);
+        // TXE clear: a frame is still waiting to be sent
+        assert_eq!(spi.wait_transfer(), Err("SPI transfer timeout".to_string()));
+        regs[SR] = SR_TXE;
+        assert_eq!(spi.wait_transfer(), Ok(()));
+    }
+
+    #[test]
+    fn a_transaction_holds_the_spi_against_other_contexts() {
+        let mut regs = [0; 18];
+        let mut rcc = [0; 36];
+        regs[CR1] = CR1_MSTR;
+        let spi = spi(&mut regs, &mut rcc, IRQType::SPI2, rcc::Clock::SPI2, None, None);
+        assert!(!spi.is_busy());
+        unsafe {
+            BUS_OWNERS[1] = Some(active_exception() + 16);
+        }
+        assert!(spi.is_busy());
+        assert_eq!(spi.write(&[1]), Err("The SPI is busy".to_string()));
+        unsafe {
+            BUS_OWNERS[1] = Some(active_exception());
+        }
+        assert!(!spi.is_busy());
+        unsafe {
+            BUS_OWNERS[1] = None;
+        }
+    }
+
+    #[test]
+    fn dma_transfers_need_aligned_16_bit_buffers() {
+        let mut dma_regs = [0u32; 0x100];
+        let mut rcc = [0; 36];
+        let controller = DMAPeripheral {
+            base_address: dma_regs.as_mut_ptr() as *mut DMARegisters,
+            isr_id: IRQType::SPI1,
+            clock: rcc::RCCPeripheral {
+                rcc: rcc.as_mut_ptr() as *mut rcc::RCCRegisters,
+                clock: rcc::Clock::DMA2
+            }
+        };
+        let stream = DMAStreamPeripheral {
+            dma: &controller,
+            base_address: unsafe { dma_regs.as_mut_ptr().offset(4) } as *mut DMAStreamRegisters,
+            channel: dma::Channel::Channel3
+        };
+        let mut regs = [0; 18];
+        regs[CR1] = CR1_DFF;
+        let mut spi_rcc = [0; 36];
+        let spi = spi(&mut regs, &mut spi_rcc, IRQType::SPI1, rcc::Clock::SPI1, Some(&stream), Some(&stream));
+
+        let tx = [0u16; 3];
+        let mut rx = [0u16; 3];
+        let tx_bytes = unsafe { slice::from_raw_parts((tx.as_ptr() as *const u8).offset(1), 4) };
+        let rx_bytes = unsafe { slice::from_raw_parts_mut(rx.as_mut_ptr() as *mut u8, 4) };
+        assert_eq!(spi.transfer_dma(tx_bytes, rx_bytes),
+                   Err("16-bit DMA transfers need halfword aligned buffers".to_string()));
+        let mut odd = [0u8; 3];
+        assert_eq!(spi.transfer_dma(&[0; 3], &mut odd), Err("Invalid transfer length".to_string()));
+    }
+}
@@ -0,0 +1,8 @@
+pub const CSR_ENABLE: u32 = 0x00000001;
+pub const CSR_TICKINT: u32 = 0x00000002;
+pub const CSR_CLKSOURCE: u32 = 0x00000004;
+pub const CSR_COUNTFLAG: u32 = 0x00010000;
+
+pub const RVR_RELOAD_MASK: u32 = 0x00FFFFFF;
+
+pub const ICSR_PENDSTSET: u32 = 0x04000000;
@@ -0,0 +1,239 @@
+use core::intrinsics;
+
+mod flags;
+
+pub use self::flags::*;
+
+use registers::*;
+use critical_section;
+
+#[repr(C)]
+pub struct SysTickRegisters {
+    control_and_status: Rw<u32>,
+    reload: Rw<u32>,
+    current: Rw<u32>,
+    calibration: Ro<u32>
+}
+
+const SYSTICK_BASE: usize = 0xE000E010;
+const SCB_ICSR: usize = 0xE000ED04;
+
+/// SysTick interrupts per second.
+pub const TICK_RATE: u32 = 1000;
+
+fn systick_get() -> &'static mut SysTickRegisters {
+    unsafe { &mut *(SYSTICK_BASE as *mut SysTickRegisters) }
+}
+
+// milliseconds since `init`
+static mut TICKS: u64 = 0;
+// HCLK cycles per tick
+static mut CYCLES_PER_TICK: u32 = 0;
+
+/// Called by the SysTick exception handler.
+pub fn on_tick() {
+    unsafe {
+        TICKS += 1;
+    }
+}
+
+/// SysTick reload value for a 1 ms tick at `hclk`.
+pub fn tick_reload(hclk: u32) -> Result<u32, &'static str> {
+    let cycles = (hclk + TICK_RATE / 2) / TICK_RATE;
+    if (cycles < 2) || (RVR_RELOAD_MASK + 1 < cycles) {
+        return Err("HCLK is out of the SysTick range.");
+    }
+    Ok(cycles - 1)
+}
+
+/// Starts (or retunes) the 1 ms tick from an `hclk` Hz core clock. The
+/// elapsed time is kept.
+pub fn init(hclk: u32) -> Result<(), &'static str> {
+    let reload = match tick_reload(hclk) {
+        Ok(reload) => reload,
+        Err(msg) => return Err(msg)
+    };
+    let systick = systick_get();
+    critical_section(|| {
+        systick.control_and_status.write(0);
+        systick.reload.write(reload);
+        systick.current.write(0);
+        unsafe {
+            CYCLES_PER_TICK = reload + 1;
+        }
+        systick.control_and_status.write(CSR_ENABLE | CSR_TICKINT | CSR_CLKSOURCE);
+    });
+    Ok(())
+}
+
+pub fn is_running() -> bool {
+    (systick_get().control_and_status.read() & CSR_ENABLE) == CSR_ENABLE
+}
+
+/// Milliseconds since the time base started.
+pub fn now() -> u64 {
+    now_us() / 1000
+}
+
+/// Microseconds since the time base started.
+pub fn now_us() -> u64 {
+    let systick = systick_get();
+    critical_section(|| unsafe {
+        let mut ticks = TICKS;
+        let cycles = CYCLES_PER_TICK;
+        if cycles == 0 {
+            return 0;
+        }
+        let mut current = systick.current.read();
+        // the counter may have wrapped without the handler running yet
+        if (intrinsics::volatile_load(SCB_ICSR as *const u32) & ICSR_PENDSTSET) != 0 {
+            current = systick.current.read();
+            ticks += 1;
+        }
+        let elapsed = (cycles - 1 - current) as u64;
+        ticks * 1000 + (elapsed * 1000) / (cycles as u64)
+    })
+}
+
+/// A point in time, in microseconds since the time base started.
+#[derive(Copy, Clone, PartialEq, PartialOrd)]
+pub struct Deadline {
+    pub at_us: u64
+}
+impl Deadline {
+    pub fn after_ms(ms: u32) -> Deadline {
+        Deadline::after_us((ms as u64) * 1000)
+    }
+
+    pub fn after_us(us: u64) -> Deadline {
+        Deadline {
+            at_us: now_us() + us
+        }
+    }
+
+    pub fn has_expired(&self) -> bool {
+        self.at_us <= now_us()
+    }
+
+    /// Microseconds left before the deadline.
+    pub fn remaining_us(&self) -> u64 {
+        let now = now_us();
+        if self.at_us <= now { 0 } else { self.at_us - now }
+    }
+}
+
+/// Polls used as a timeout when SysTick is stopped, per millisecond. Loose
+/// (it assumes a slow core), but it keeps the wait bounded.
+const POLLS_PER_MS: u64 = 4_000;
+
+/// Counts the HCLK cycles elapsed from the SysTick counter itself, so it also
+/// works with interrupts masked. It must be polled at least once per tick.
+struct CycleCounter {
+    last: u32,
+    elapsed: u64
+}
+impl CycleCounter {
+    fn start() -> CycleCounter {
+        CycleCounter {
+            last: systick_get().current.read(),
+            elapsed: 0
+        }
+    }
+
+    fn poll(&mut self, cycles_per_tick: u32) -> u64 {
+        let current = systick_get().current.read();
+        // the counter counts down and reloads after 0
+        let delta = if current <= self.last {
+            self.last - current
+        } else {
+            self.last + cycles_per_tick - current
+        };
+        self.last = current;
+        self.elapsed += delta as u64;
+        self.elapsed
+    }
+}
+
+/// Calls `poll` until it returns true or `us` microseconds elapsed. Returns
+/// whether `poll` succeeded.
+fn poll_for<F: FnMut() -> bool>(us: u64, mut poll: F) -> bool {
+    let cycles_per_tick = unsafe { CYCLES_PER_TICK };
+    if (cycles_per_tick == 0) || !is_running() {
+        let mut polls = (us * POLLS_PER_MS + 999) / 1000;
+        while polls != 0 {
+            if poll() {
+                return true;
+            }
+            polls -= 1;
+        }
+        return poll();
+    }
+
+    let timeout = (us * (cycles_per_tick as u64) + 999) / 1000;
+    let mut counter = CycleCounter::start();
+    loop {
+        if poll() {
+            return true;
+        }
+        if timeout <= counter.poll(cycles_per_tick) {
+            // it may have become true while we were preempted
+            return poll();
+        }
+    }
+}
+
+/// Polls `condition` until it holds or `timeout_ms` elapsed. Returns whether
+/// it held.
+///
+/// The time is measured on the SysTick counter, not its interrupt, so this
+/// can be used with interrupts masked. When SysTick is stopped the timeout
+/// becomes a bounded number of polls.
+pub fn wait_for<F: Fn() -> bool>(timeout_ms: u32, condition: F) -> bool {
+    poll_for((timeout_ms as u64) * 1000, || condition())
+}
+
+fn busy_wait(us: u64) {
+    poll_for(us, || false);
+}
+
+/// Busy-waits for `us` microseconds. Interrupts may be masked.
+pub fn delay_us(us: u32) {
+    busy_wait(us as u64);
+}
+
+/// Busy-waits for `ms` milliseconds. Interrupts may be masked.
+pub fn delay_ms(ms: u32) {
+    busy_wait((ms as u64) * 1000);
+}
+
+/// Sleeps until `deadline` with `wfi`, waking up on each interrupt (at least
+/// every tick) to check it. This only idles the core, it does not yield to
+/// other threads. Must not be called with interrupts masked.
+pub fn sleep_until(deadline: Deadline) {
+    while !deadline.has_expired() {
+        wait_for_interrupt();
+    }
+}
+
+#[cfg(target_arch = "arm")]
+fn wait_for_interrupt() {
+    unsafe {
+        asm!("wfi" :::: "volatile");
+    }
+}
+/// Host builds (unit tests) just poll.
+#[cfg(not(target_arch = "arm"))]
+fn wait_for_interrupt() {
+}
+
+/// Sleeps for `ms` milliseconds, idling the core with `wfi` between
+/// interrupts.
+pub fn sleep_ms(ms: u32) {
+    sleep_until(Deadline::after_ms(ms));
+}
+
+/// Sleeps for `us` microseconds, idling the core with `wfi` between
+/// interrupts.
+pub fn sleep_us(us: u32) {
+    sleep_until(Deadline::after_us(us as u64));
+}
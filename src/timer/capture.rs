@@ -0,0 +1,333 @@
+use collections::string::String;
+use collections::string::ToString;
+
+use Peripheral;
+use gpio::PinPeripheral;
+use super::*;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Edge {
+    Rising,
+    Falling,
+    Both
+}
+impl Edge {
+    /// CCxP/CCxNP bits for this edge.
+    fn ccer(self) -> u32 {
+        match self {
+            Edge::Rising => 0,
+            Edge::Falling => CCER_CCP,
+            Edge::Both => CCER_CCP | CCER_CCNP
+        }
+    }
+}
+
+/// Number of edges per capture.
+#[derive(Copy, Clone, PartialEq)]
+pub enum InputPrescaler {
+    Div1 = 0,
+    Div2 = 1,
+    Div4 = 2,
+    Div8 = 3
+}
+
+// CCxS values
+const CCS_DIRECT: u32 = 1;
+const CCS_INDIRECT: u32 = 2;
+
+// SMCR trigger selection and slave modes
+const TS_TI1FP1: u32 = 5;
+const TS_TI2FP2: u32 = 6;
+const SMS_RESET: u32 = 4;
+
+/// A captured counter value.
+#[derive(Copy, Clone)]
+pub struct Capture {
+    pub value: u32,
+    /// At least one capture was lost before this one.
+    pub overcapture: bool
+}
+
+/// Ticks between two captures of a counter reloading at `reload`, assuming
+/// it wrapped at most once.
+pub fn capture_delta(earlier: u32, later: u32, reload: u32) -> u32 {
+    if earlier <= later {
+        later - earlier
+    } else {
+        ((reload as u64) + 1 - (earlier as u64) + (later as u64)) as u32
+    }
+}
+
+fn check_filter(filter: u8) -> Result<(), String> {
+    if filter > 15 {
+        return Err("The input filter must be in [0; 15].".to_string());
+    }
+    Ok(())
+}
+
+impl TimerPeripheral {
+    /// Sets the counter clock to the input clock divided by `prescaler + 1`
+    /// and lets it count over its whole range, as used for input capture.
+    pub fn configure_capture(&self, prescaler: u16) -> Result<(), String> {
+        if let Err(msg) = self.require(TimerType::GeneralPurpose) {
+            return Err(msg);
+        }
+        self.stop();
+        let tim = self.registers();
+        tim.control1.update(CR1_URS, CR1_URS | CR1_CMS_MASK | CR1_DIR | CR1_OPM);
+        tim.prescaler.write(prescaler as u32);
+        tim.auto_reload.write(self.max_reload());
+        tim.event_generation.write(EGR_UG);
+        Ok(())
+    }
+
+    /// Counter ticks per second.
+    pub fn counter_clock(&self) -> u32 {
+        let psc = self.registers().prescaler.read() as usize;
+        (self.input_clock() / (psc + 1)) as u32
+    }
+}
+
+/// Captures the counter on the edges of a timer input.
+///
+/// Captured values are either polled with `poll` or, after `enable_interrupt`,
+/// extracted from the flags given to the timer callback with `from_flags`.
+pub struct CaptureChannel<'a> {
+    pub timer: &'a TimerPeripheral,
+    pub channel: Channel,
+    pub edge: Edge,
+    pub prescaler: InputPrescaler,
+    /// Number of samples the input must be stable for, see ICxF in RM0033.
+    pub filter: u8,
+    pub pin: Option<&'a PinPeripheral<'a>>
+}
+unsafe impl<'a> Sync for CaptureChannel<'a> {}
+
+impl<'a> CaptureChannel<'a> {
+    pub fn enable(&self) {
+        self.timer.update_ccer(self.channel, CCER_CCE, CCER_CCE);
+    }
+
+    pub fn disable(&self) {
+        self.timer.update_ccer(self.channel, 0, CCER_CCE);
+    }
+
+    /// Raises the timer interrupt on each capture.
+    pub fn enable_interrupt(&self) {
+        let flag = self.channel.interrupt_flag();
+        self.timer.registers().dma_interrupt_enable.update(flag, flag);
+    }
+
+    pub fn disable_interrupt(&self) {
+        let flag = self.channel.interrupt_flag();
+        self.timer.registers().dma_interrupt_enable.update(0, flag);
+    }
+
+    /// Returns the latest capture, if any since the last call.
+    pub fn poll(&self) -> Option<Capture> {
+        let tim = self.timer.registers();
+        let flags = tim.status.read();
+        if (flags & self.channel.interrupt_flag()) == 0 {
+            return None;
+        }
+        // reading CCRx clears CCxIF
+        let value = self.timer.capture_compare(self.channel);
+        let overcapture = (flags & self.channel.overcapture_flag()) != 0;
+        if overcapture {
+            tim.status.write(!self.channel.overcapture_flag());
+        }
+        Some(Capture {
+            value: value,
+            overcapture: overcapture
+        })
+    }
+
+    /// Returns the capture reported by `flags`, as given to a `TimerCallback`.
+    pub fn from_flags(&self, flags: u32) -> Option<Capture> {
+        if (flags & self.channel.interrupt_flag()) == 0 {
+            return None;
+        }
+        Some(Capture {
+            value: self.timer.capture_compare(self.channel),
+            overcapture: (flags & self.channel.overcapture_flag()) != 0
+        })
+    }
+}
+
+impl<'a> Peripheral for CaptureChannel<'a> {
+    fn init(&self) -> Result<(), String> {
+        if let Err(msg) = self.timer.require(TimerType::GeneralPurpose) {
+            return Err(msg);
+        }
+        if let Err(msg) = self.timer.require_channel(self.channel) {
+            return Err(msg);
+        }
+        if let Err(msg) = check_filter(self.filter) {
+            return Err(msg);
+        }
+        if let Some(pin) = self.pin {
+            if let Err(msg) = self.timer.check_pin(pin, self.timer.channel_signal(self.channel)) {
+                return Err(msg);
+            }
+        }
+        init_peripheral![self.pin];
+
+        self.disable();
+        self.timer.update_ccmr(
+            self.channel,
+            CCS_DIRECT |
+                ((self.prescaler as u32) << CCMR_ICPSC_SHIFT) |
+                ((self.filter as u32) << CCMR_ICF_SHIFT),
+            CCMR_CHANNEL_MASK
+        );
+        self.timer.update_ccer(self.channel, self.edge.ccer(), CCER_CCP | CCER_CCNP);
+        let flags = self.channel.interrupt_flag() | self.channel.overcapture_flag();
+        self.timer.registers().status.write(!flags);
+
+        Ok(())
+    }
+
+    fn deinit(&self) -> Result<(), String> {
+        self.disable_interrupt();
+        self.disable();
+        self.timer.update_ccmr(self.channel, 0, CCMR_CHANNEL_MASK);
+        Ok(())
+    }
+}
+
+/// Period and active time of a PWM signal, in counter ticks.
+#[derive(Copy, Clone)]
+pub struct PwmMeasurement {
+    pub period: u32,
+    pub duty: u32
+}
+
+/// Measures a PWM signal on TI1 or TI2 using channels 1 and 2: the counter is
+/// reset on each active edge, one channel captures the period and the other
+/// the active time.
+///
+/// The counter must not overflow within a period, pick the prescaler given to
+/// `configure_capture` accordingly. 32-bit TIM2 and TIM5 rarely need one.
+pub struct PwmInput<'a> {
+    pub timer: &'a TimerPeripheral,
+    /// `Channel1` for TI1, `Channel2` for TI2.
+    pub input: Channel,
+    /// Edge starting the active part of the period.
+    pub active_edge: Edge,
+    pub filter: u8,
+    pub pin: Option<&'a PinPeripheral<'a>>
+}
+unsafe impl<'a> Sync for PwmInput<'a> {}
+
+impl<'a> PwmInput<'a> {
+    /// Channels capturing the period and the active time.
+    fn channels(&self) -> (Channel, Channel) {
+        if self.input == Channel::Channel1 {
+            (Channel::Channel1, Channel::Channel2)
+        } else {
+            (Channel::Channel2, Channel::Channel1)
+        }
+    }
+
+    pub fn start(&self) {
+        let (period, duty) = self.channels();
+        self.timer.update_ccer(period, CCER_CCE, CCER_CCE);
+        self.timer.update_ccer(duty, CCER_CCE, CCER_CCE);
+        self.timer.start();
+    }
+
+    pub fn stop(&self) {
+        let (period, duty) = self.channels();
+        self.timer.stop();
+        self.timer.update_ccer(period, 0, CCER_CCE);
+        self.timer.update_ccer(duty, 0, CCER_CCE);
+    }
+
+    /// Returns the last complete measurement. `None` if no period was
+    /// captured since the last call or if the counter overflowed, meaning the
+    /// signal is too slow or stuck.
+    pub fn measure(&self) -> Option<PwmMeasurement> {
+        let (period, duty) = self.channels();
+        let tim = self.timer.registers();
+        let flags = tim.status.read();
+        if (flags & SR_UIF) != 0 {
+            tim.status.write(!(SR_UIF | period.interrupt_flag() | duty.interrupt_flag()));
+            return None;
+        }
+        if (flags & period.interrupt_flag()) == 0 {
+            return None;
+        }
+        tim.status.write(!(period.overcapture_flag() | duty.overcapture_flag()));
+        Some(PwmMeasurement {
+            period: self.timer.capture_compare(period),
+            duty: self.timer.capture_compare(duty)
+        })
+    }
+
+    /// Frequency of the measured signal in Hz, with its duty cycle in
+    /// per mille.
+    pub fn frequency(&self) -> Option<(u32, u32)> {
+        match self.measure() {
+            Some(m) if m.period != 0 => {
+                let clk = self.timer.counter_clock() as u64;
+                let duty = ((m.duty as u64) * 1000) / (m.period as u64);
+                Some(((clk / (m.period as u64)) as u32, duty as u32))
+            },
+            _ => None
+        }
+    }
+}
+
+impl<'a> Peripheral for PwmInput<'a> {
+    fn init(&self) -> Result<(), String> {
+        if let Err(msg) = self.timer.require(TimerType::GeneralPurpose) {
+            return Err(msg);
+        }
+        if let Err(msg) = self.timer.require_channel(Channel::Channel2) {
+            return Err(msg);
+        }
+        if (self.input != Channel::Channel1) && (self.input != Channel::Channel2) {
+            return Err("PWM input is only available on TI1 and TI2.".to_string());
+        }
+        if self.active_edge == Edge::Both {
+            return Err("PWM input needs a single active edge.".to_string());
+        }
+        if let Err(msg) = check_filter(self.filter) {
+            return Err(msg);
+        }
+        if let Some(pin) = self.pin {
+            if let Err(msg) = self.timer.check_pin(pin, self.timer.channel_signal(self.input)) {
+                return Err(msg);
+            }
+        }
+        init_peripheral![self.pin];
+
+        let (period, duty) = self.channels();
+        let inactive_edge = if self.active_edge == Edge::Rising { Edge::Falling } else { Edge::Rising };
+        let filter = (self.filter as u32) << CCMR_ICF_SHIFT;
+
+        self.stop();
+        self.timer.update_ccmr(period, CCS_DIRECT | filter, CCMR_CHANNEL_MASK);
+        self.timer.update_ccmr(duty, CCS_INDIRECT | filter, CCMR_CHANNEL_MASK);
+        self.timer.update_ccer(period, self.active_edge.ccer(), CCER_CCP | CCER_CCNP);
+        self.timer.update_ccer(duty, inactive_edge.ccer(), CCER_CCP | CCER_CCNP);
+
+        // reset the counter on the active edge
+        let ts = if self.input == Channel::Channel1 { TS_TI1FP1 } else { TS_TI2FP2 };
+        self.timer.registers().slave_mode_control.update(
+            (ts << SMCR_TS_SHIFT) | SMS_RESET,
+            SMCR_TS_MASK | SMCR_SMS_MASK
+        );
+        self.timer.registers().status.write(0);
+
+        Ok(())
+    }
+
+    fn deinit(&self) -> Result<(), String> {
+        self.stop();
+        self.timer.registers().slave_mode_control.update(0, SMCR_TS_MASK | SMCR_SMS_MASK);
+        self.timer.update_ccmr(Channel::Channel1, 0, CCMR_CHANNEL_MASK);
+        self.timer.update_ccmr(Channel::Channel2, 0, CCMR_CHANNEL_MASK);
+        Ok(())
+    }
+}
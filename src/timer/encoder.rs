@@ -0,0 +1,262 @@
+use collections::string::String;
+use collections::string::ToString;
+
+use Peripheral;
+use gpio::PinPeripheral;
+use super::*;
+
+/// Edges the counter counts on (SMS).
+#[derive(Copy, Clone, PartialEq)]
+pub enum EncoderMode {
+    /// TI1 edges, x2 resolution.
+    TI1 = 1,
+    /// TI2 edges, x2 resolution.
+    TI2 = 2,
+    /// Both inputs edges, x4 resolution.
+    Both = 3
+}
+
+struct EncoderState {
+    /// Counter wraps, signed.
+    wraps: i64,
+    last_position: i64,
+    velocity: i32
+}
+impl EncoderState {
+    const fn new() -> EncoderState {
+        EncoderState {
+            wraps: 0,
+            last_position: 0,
+            velocity: 0
+        }
+    }
+}
+
+// indexed by timer number - 1, TIM1 to TIM8
+static mut STATES: [EncoderState; 8] = [
+    EncoderState::new(), EncoderState::new(), EncoderState::new(), EncoderState::new(),
+    EncoderState::new(), EncoderState::new(), EncoderState::new(), EncoderState::new()
+];
+
+/// Position from the counter value and its number of wraps.
+pub fn extend_position(wraps: i64, counter: u32, reload: u32) -> i64 {
+    wraps * ((reload as i64) + 1) + (counter as i64)
+}
+
+/// Direction of a counter wrap seen late, from the side of the range the
+/// counter is on: +1 after an overflow, -1 after an underflow. DIR cannot
+/// tell, the shaft may have turned back since.
+pub fn wrap_direction(counter: u32, reload: u32) -> i64 {
+    if counter < reload / 2 { 1 } else { -1 }
+}
+
+fn on_update(id: usize, flags: u32) {
+    if (flags & SR_UIF) == 0 {
+        return;
+    }
+    unsafe {
+        let tim = &*HANDLERS[id].registers;
+        STATES[id].wraps += wrap_direction(tim.counter.read(), tim.auto_reload.read());
+    }
+}
+
+/// Quadrature encoder on TI1 and TI2 of TIM1 to TIM5 or TIM8.
+///
+/// The hardware counter is extended to 64 bits by counting its wraps from the
+/// update interrupt. The velocity is computed by `sample`, which must be
+/// called `sample_rate` times per second, typically from a periodic timer.
+pub struct Encoder<'a> {
+    pub timer: &'a TimerPeripheral,
+    pub mode: EncoderMode,
+    /// Input filter, see ICxF in RM0033.
+    pub filter: u8,
+    /// Counts down when A leads B.
+    pub invert: bool,
+    pub sample_rate: u32,
+    pub pin_a: Option<&'a PinPeripheral<'a>>,
+    pub pin_b: Option<&'a PinPeripheral<'a>>
+}
+unsafe impl<'a> Sync for Encoder<'a> {}
+
+impl<'a> Encoder<'a> {
+    fn id(&self) -> Option<usize> {
+        match self.timer.handler_id() {
+            Some(id) if id < 8 => Some(id),
+            _ => None
+        }
+    }
+
+    pub fn start(&self) {
+        self.timer.start();
+    }
+
+    pub fn stop(&self) {
+        self.timer.stop();
+    }
+
+    /// Signed position in counts, 0 on an invalid timer.
+    pub fn position(&self) -> i64 {
+        let id = match self.id() {
+            Some(id) => id,
+            None => return 0
+        };
+        let reload = self.timer.auto_reload();
+        let tim = self.timer.registers();
+        ::critical_section(|| {
+            let mut wraps = unsafe { STATES[id].wraps };
+            let mut pending;
+            let mut counter;
+            // read again if a wrap happened in between, the counter must be
+            // the one following the pending wrap
+            loop {
+                pending = (tim.status.read() & SR_UIF) != 0;
+                counter = tim.counter.read();
+                if pending == ((tim.status.read() & SR_UIF) != 0) {
+                    break;
+                }
+            }
+            if pending {
+                wraps += wrap_direction(counter, reload);
+            }
+            extend_position(wraps, counter, reload)
+        })
+    }
+
+    /// Sets the current position to `position`.
+    pub fn set_position(&self, position: i64) {
+        let id = match self.id() {
+            Some(id) => id,
+            None => return
+        };
+        let range = (self.timer.auto_reload() as i64) + 1;
+        let tim = self.timer.registers();
+        ::critical_section(|| {
+            let mut wraps = position / range;
+            let mut counter = position % range;
+            if counter < 0 {
+                counter += range;
+                wraps -= 1;
+            }
+            tim.counter.write(counter as u32);
+            tim.status.write(!SR_UIF);
+            unsafe {
+                STATES[id].wraps = wraps;
+                STATES[id].last_position = position;
+                STATES[id].velocity = 0;
+            }
+        });
+    }
+
+    /// Updates the velocity from the distance since the last sample.
+    pub fn sample(&self) {
+        let id = match self.id() {
+            Some(id) => id,
+            None => return
+        };
+        let position = self.position();
+        let rate = self.sample_rate as i64;
+        ::critical_section(|| unsafe {
+            let state = &mut STATES[id];
+            state.velocity = ((position - state.last_position) * rate) as i32;
+            state.last_position = position;
+        });
+    }
+
+    /// Velocity in counts per second, as of the last sample.
+    pub fn velocity(&self) -> i32 {
+        match self.id() {
+            Some(id) => unsafe { STATES[id].velocity },
+            None => 0
+        }
+    }
+}
+
+impl<'a> Peripheral for Encoder<'a> {
+    fn init(&self) -> Result<(), String> {
+        if !self.timer.timer_type.has_encoder_mode() {
+            return Err("Encoder mode is not available on basic timers.".to_string());
+        }
+        match self.timer.number() {
+            1...5 | 8 => {},
+            _ => return Err("Encoder mode is only available on TIM1 to TIM5 and TIM8.".to_string())
+        }
+        if self.filter > 15 {
+            return Err("The input filter must be in [0; 15].".to_string());
+        }
+        if self.sample_rate == 0 {
+            return Err("The velocity sample rate must not be null.".to_string());
+        }
+        if let Some(pin) = self.pin_a {
+            if let Err(msg) = self.timer.check_pin(pin, self.timer.channel_signal(Channel::Channel1)) {
+                return Err(msg);
+            }
+        }
+        if let Some(pin) = self.pin_b {
+            if let Err(msg) = self.timer.check_pin(pin, self.timer.channel_signal(Channel::Channel2)) {
+                return Err(msg);
+            }
+        }
+        init_peripheral![self.pin_a, self.pin_b];
+
+        self.stop();
+        let tim = self.timer.registers();
+        // TI1 -> IC1 and TI2 -> IC2, non-inverted
+        let ccmr = 1 | ((self.filter as u32) << CCMR_ICF_SHIFT);
+        self.timer.update_ccmr(Channel::Channel1, ccmr, CCMR_CHANNEL_MASK);
+        self.timer.update_ccmr(Channel::Channel2, ccmr, CCMR_CHANNEL_MASK);
+        let polarity = if self.invert { CCER_CCP } else { 0 };
+        self.timer.update_ccer(Channel::Channel1, polarity, CCER_CHANNEL_MASK);
+        self.timer.update_ccer(Channel::Channel2, 0, CCER_CHANNEL_MASK);
+
+        tim.slave_mode_control.update(self.mode as u32, SMCR_TS_MASK | SMCR_SMS_MASK);
+        tim.control1.update(CR1_URS, CR1_URS | CR1_CMS_MASK | CR1_OPM | CR1_ARPE);
+        tim.prescaler.write(0);
+        tim.auto_reload.write(self.timer.max_reload());
+        tim.event_generation.write(EGR_UG);
+
+        self.set_position(0);
+        if let Some(id) = self.id() {
+            if let Err(msg) = self.timer.attach(on_update, id) {
+                return Err(msg);
+            }
+        }
+        tim.dma_interrupt_enable.update(DIER_UIE, DIER_UIE);
+
+        Ok(())
+    }
+
+    fn deinit(&self) -> Result<(), String> {
+        self.stop();
+        self.timer.detach();
+        self.timer.registers().slave_mode_control.update(0, SMCR_SMS_MASK);
+        self.timer.update_ccmr(Channel::Channel1, 0, CCMR_CHANNEL_MASK);
+        self.timer.update_ccmr(Channel::Channel2, 0, CCMR_CHANNEL_MASK);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extend_position_counts_whole_ranges() {
+        assert_eq!(extend_position(0, 0, 0xFFFF), 0);
+        assert_eq!(extend_position(0, 0xFFFF, 0xFFFF), 0xFFFF);
+        assert_eq!(extend_position(1, 5, 0xFFFF), 0x10005);
+        assert_eq!(extend_position(-1, 0xFFFF, 0xFFFF), -1);
+        assert_eq!(extend_position(-2, 999, 999), -1001);
+    }
+
+    #[test]
+    fn wraps_are_told_from_the_counter_side() {
+        // just overflowed, even if the shaft has since turned back
+        assert_eq!(wrap_direction(0, 0xFFFF), 1);
+        assert_eq!(wrap_direction(3, 0xFFFF), 1);
+        // just underflowed
+        assert_eq!(wrap_direction(0xFFFF, 0xFFFF), -1);
+        assert_eq!(wrap_direction(0xFFFA, 0xFFFF), -1);
+        assert_eq!(wrap_direction(100, 999), 1);
+        assert_eq!(wrap_direction(900, 999), -1);
+    }
+}
@@ -0,0 +1,85 @@
+pub const CR1_CEN: u32 = 0x00000001;
+pub const CR1_UDIS: u32 = 0x00000002;
+pub const CR1_URS: u32 = 0x00000004;
+pub const CR1_OPM: u32 = 0x00000008;
+pub const CR1_DIR: u32 = 0x00000010;
+pub const CR1_CMS_MASK: u32 = 0x00000060;
+pub const CR1_CMS_SHIFT: u32 = 5;
+pub const CR1_ARPE: u32 = 0x00000080;
+pub const CR1_CKD_MASK: u32 = 0x00000300;
+pub const CR1_CKD_SHIFT: u32 = 8;
+
+pub const CR2_CCPC: u32 = 0x00000001;
+pub const CR2_CCUS: u32 = 0x00000004;
+pub const CR2_CCDS: u32 = 0x00000008;
+pub const CR2_MMS_MASK: u32 = 0x00000070;
+pub const CR2_MMS_SHIFT: u32 = 4;
+pub const CR2_TI1S: u32 = 0x00000080;
+
+pub const SMCR_SMS_MASK: u32 = 0x00000007;
+pub const SMCR_TS_MASK: u32 = 0x00000070;
+pub const SMCR_TS_SHIFT: u32 = 4;
+pub const SMCR_MSM: u32 = 0x00000080;
+pub const SMCR_ETF_MASK: u32 = 0x00000F00;
+pub const SMCR_ETPS_MASK: u32 = 0x00003000;
+pub const SMCR_ECE: u32 = 0x00004000;
+pub const SMCR_ETP: u32 = 0x00008000;
+
+pub const DIER_UIE: u32 = 0x00000001;
+pub const DIER_CC1IE: u32 = 0x00000002;
+pub const DIER_CC2IE: u32 = 0x00000004;
+pub const DIER_CC3IE: u32 = 0x00000008;
+pub const DIER_CC4IE: u32 = 0x00000010;
+pub const DIER_COMIE: u32 = 0x00000020;
+pub const DIER_TIE: u32 = 0x00000040;
+pub const DIER_BIE: u32 = 0x00000080;
+pub const DIER_UDE: u32 = 0x00000100;
+pub const DIER_INTERRUPTS: u32 = 0x000000FF;
+
+pub const SR_UIF: u32 = 0x00000001;
+pub const SR_CC1IF: u32 = 0x00000002;
+pub const SR_CC2IF: u32 = 0x00000004;
+pub const SR_CC3IF: u32 = 0x00000008;
+pub const SR_CC4IF: u32 = 0x00000010;
+pub const SR_COMIF: u32 = 0x00000020;
+pub const SR_TIF: u32 = 0x00000040;
+pub const SR_BIF: u32 = 0x00000080;
+pub const SR_CC1OF: u32 = 0x00000200;
+pub const SR_CC2OF: u32 = 0x00000400;
+pub const SR_CC3OF: u32 = 0x00000800;
+pub const SR_CC4OF: u32 = 0x00001000;
+
+pub const EGR_UG: u32 = 0x00000001;
+pub const EGR_CC1G: u32 = 0x00000002;
+pub const EGR_COMG: u32 = 0x00000020;
+pub const EGR_TG: u32 = 0x00000040;
+pub const EGR_BG: u32 = 0x00000080;
+
+pub const BDTR_DTG_MASK: u32 = 0x000000FF;
+pub const BDTR_LOCK_MASK: u32 = 0x00000300;
+pub const BDTR_OSSI: u32 = 0x00000400;
+pub const BDTR_OSSR: u32 = 0x00000800;
+pub const BDTR_BKE: u32 = 0x00001000;
+pub const BDTR_BKP: u32 = 0x00002000;
+pub const BDTR_AOE: u32 = 0x00004000;
+pub const BDTR_MOE: u32 = 0x00008000;
+
+// Capture/compare mode and enable fields, for channel 1. Channels 2 and 4 are
+// 8 bits higher in CCMR1/CCMR2, use `ccer_shift` for CCER.
+pub const CCMR_CCS_MASK: u32 = 0x00000003;
+pub const CCMR_OCFE: u32 = 0x00000004;
+pub const CCMR_OCPE: u32 = 0x00000008;
+pub const CCMR_OCM_MASK: u32 = 0x00000070;
+pub const CCMR_OCM_SHIFT: u32 = 4;
+pub const CCMR_OCCE: u32 = 0x00000080;
+pub const CCMR_ICPSC_MASK: u32 = 0x0000000C;
+pub const CCMR_ICPSC_SHIFT: u32 = 2;
+pub const CCMR_ICF_MASK: u32 = 0x000000F0;
+pub const CCMR_ICF_SHIFT: u32 = 4;
+pub const CCMR_CHANNEL_MASK: u32 = 0x000000FF;
+
+pub const CCER_CCE: u32 = 0x00000001;
+pub const CCER_CCP: u32 = 0x00000002;
+pub const CCER_CCNE: u32 = 0x00000004;
+pub const CCER_CCNP: u32 = 0x00000008;
+pub const CCER_CHANNEL_MASK: u32 = 0x0000000F;
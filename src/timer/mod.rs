@@ -0,0 +1,583 @@
+use collections::string::String;
+use collections::string::ToString;
+
+mod flags;
+/// PWM output
+pub mod pwm;
+/// Input capture and PWM input
+pub mod capture;
+/// Quadrature encoder interface
+pub mod encoder;
+/// Master/slave synchronisation
+pub mod sync;
+
+pub use self::flags::*;
+
+use rcc;
+use IRQType;
+use Peripheral;
+use registers::*;
+use gpio::PinPeripheral;
+use gpio::af::{self, Signal};
+
+// Superset of the TIM1 to TIM14 register maps, registers a timer does not
+// implement are reserved.
+#[repr(C)]
+pub struct TimerRegisters {
+    control1: Rw<u32>,
+    control2: Rw<u32>,
+    slave_mode_control: Rw<u32>,
+    dma_interrupt_enable: Rw<u32>,
+    status: Rw<u32>,
+    event_generation: Wo<u32>,
+    capture_compare_mode1: Rw<u32>,
+    capture_compare_mode2: Rw<u32>,
+    capture_compare_enable: Rw<u32>,
+    counter: Rw<u32>,
+    prescaler: Rw<u32>,
+    auto_reload: Rw<u32>,
+    repetition_counter: Rw<u32>,
+    capture_compare: [Rw<u32>; 4],
+    break_dead_time: Rw<u32>,
+    dma_control: Rw<u32>,
+    dma_address: Rw<u32>,
+    option: Rw<u32>
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum TimerType {
+    Basic,
+    GeneralPurpose,
+    Advanced
+}
+impl TimerType {
+    /// Basic timers have no inputs, hence no encoder mode.
+    pub fn has_encoder_mode(self) -> bool {
+        self != TimerType::Basic
+    }
+}
+
+/// Capture/compare channel.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Channel {
+    Channel1 = 0,
+    Channel2 = 1,
+    Channel3 = 2,
+    Channel4 = 3
+}
+impl Channel {
+    /// Shift of this channel's fields in CCMR1/CCMR2.
+    pub fn ccmr_shift(self) -> u32 {
+        ((self as u32) & 1) * 8
+    }
+    /// Shift of this channel's fields in CCER.
+    pub fn ccer_shift(self) -> u32 {
+        (self as u32) * 4
+    }
+    /// CCxIF flag in SR, CCxIE in DIER.
+    pub fn interrupt_flag(self) -> u32 {
+        SR_CC1IF << (self as u32)
+    }
+    /// CCxOF flag in SR.
+    pub fn overcapture_flag(self) -> u32 {
+        SR_CC1OF << (self as u32)
+    }
+}
+
+/// Called from the timer's ISR with the registered argument and the SR flags
+/// that were raised (and already cleared).
+pub type TimerCallback = fn(usize, u32);
+
+struct TimerHandler {
+    registers: *mut TimerRegisters,
+    callback: Option<TimerCallback>,
+    argument: usize
+}
+impl TimerHandler {
+    const fn new() -> TimerHandler {
+        TimerHandler {
+            registers: 0 as *mut TimerRegisters,
+            callback: None,
+            argument: 0
+        }
+    }
+}
+
+// indexed by timer number - 1
+static mut HANDLERS: [TimerHandler; 14] = [
+    TimerHandler::new(), TimerHandler::new(), TimerHandler::new(), TimerHandler::new(),
+    TimerHandler::new(), TimerHandler::new(), TimerHandler::new(), TimerHandler::new(),
+    TimerHandler::new(), TimerHandler::new(), TimerHandler::new(), TimerHandler::new(),
+    TimerHandler::new(), TimerHandler::new()
+];
+
+unsafe fn on_interrupt(timer: usize) {
+    let handler = &HANDLERS[timer - 1];
+    if handler.registers.is_null() {
+        return;
+    }
+    let tim = &mut *handler.registers;
+    let enabled = tim.dma_interrupt_enable.read() & DIER_INTERRUPTS;
+    let flags = tim.status.read();
+    if (flags & enabled) == 0 {
+        return;
+    }
+    // flags are cleared by writing 0, overcapture flags are reported as is
+    tim.status.write(!flags);
+    if let Some(callback) = handler.callback {
+        callback(handler.argument, flags);
+    }
+}
+
+pub unsafe extern "C" fn tim1_brk_tim9_handler() {
+    on_interrupt(1);
+    on_interrupt(9);
+}
+pub unsafe extern "C" fn tim1_up_tim10_handler() {
+    on_interrupt(1);
+    on_interrupt(10);
+}
+pub unsafe extern "C" fn tim1_trg_com_tim11_handler() {
+    on_interrupt(1);
+    on_interrupt(11);
+}
+pub unsafe extern "C" fn tim1_cc_handler() {
+    on_interrupt(1);
+}
+pub unsafe extern "C" fn tim2_handler() {
+    on_interrupt(2);
+}
+pub unsafe extern "C" fn tim3_handler() {
+    on_interrupt(3);
+}
+pub unsafe extern "C" fn tim4_handler() {
+    on_interrupt(4);
+}
+pub unsafe extern "C" fn tim5_handler() {
+    on_interrupt(5);
+}
+pub unsafe extern "C" fn tim6_dac_handler() {
+    on_interrupt(6);
+}
+pub unsafe extern "C" fn tim7_handler() {
+    on_interrupt(7);
+}
+pub unsafe extern "C" fn tim8_brk_tim12_handler() {
+    on_interrupt(8);
+    on_interrupt(12);
+}
+pub unsafe extern "C" fn tim8_up_tim13_handler() {
+    on_interrupt(8);
+    on_interrupt(13);
+}
+pub unsafe extern "C" fn tim8_trg_com_tim14_handler() {
+    on_interrupt(8);
+    on_interrupt(14);
+}
+pub unsafe extern "C" fn tim8_cc_handler() {
+    on_interrupt(8);
+}
+
+static TIM1_IRQS: [IRQType; 4] = [IRQType::TIM1_BRK_TIM9, IRQType::TIM1_UP_TIM10,
+                                  IRQType::TIM1_TRG_COM_TIM11, IRQType::TIM1_CC];
+static TIM2_IRQS: [IRQType; 1] = [IRQType::TIM2];
+static TIM3_IRQS: [IRQType; 1] = [IRQType::TIM3];
+static TIM4_IRQS: [IRQType; 1] = [IRQType::TIM4];
+static TIM5_IRQS: [IRQType; 1] = [IRQType::TIM5];
+static TIM6_IRQS: [IRQType; 1] = [IRQType::TIM6_DAC];
+static TIM7_IRQS: [IRQType; 1] = [IRQType::TIM7];
+static TIM8_IRQS: [IRQType; 4] = [IRQType::TIM8_BRK_TIM12, IRQType::TIM8_UP_TIM13,
+                                  IRQType::TIM8_TRG_COM_TIM14, IRQType::TIM8_CC];
+static TIM9_IRQS: [IRQType; 1] = [IRQType::TIM1_BRK_TIM9];
+static TIM10_IRQS: [IRQType; 1] = [IRQType::TIM1_UP_TIM10];
+static TIM11_IRQS: [IRQType; 1] = [IRQType::TIM1_TRG_COM_TIM11];
+static TIM12_IRQS: [IRQType; 1] = [IRQType::TIM8_BRK_TIM12];
+static TIM13_IRQS: [IRQType; 1] = [IRQType::TIM8_UP_TIM13];
+static TIM14_IRQS: [IRQType; 1] = [IRQType::TIM8_TRG_COM_TIM14];
+static NO_IRQS: [IRQType; 0] = [];
+static IRQS: [&'static [IRQType]; 14] = [
+    &TIM1_IRQS, &TIM2_IRQS, &TIM3_IRQS, &TIM4_IRQS, &TIM5_IRQS, &TIM6_IRQS, &TIM7_IRQS,
+    &TIM8_IRQS, &TIM9_IRQS, &TIM10_IRQS, &TIM11_IRQS, &TIM12_IRQS, &TIM13_IRQS, &TIM14_IRQS
+];
+
+// CH1 to CH4 signals of each timer
+static CHANNEL_SIGNALS: [[Option<Signal>; 4]; 14] = [
+    [Some(Signal::TIM1_CH1), Some(Signal::TIM1_CH2), Some(Signal::TIM1_CH3), Some(Signal::TIM1_CH4)],
+    [Some(Signal::TIM2_CH1_ETR), Some(Signal::TIM2_CH2), Some(Signal::TIM2_CH3), Some(Signal::TIM2_CH4)],
+    [Some(Signal::TIM3_CH1), Some(Signal::TIM3_CH2), Some(Signal::TIM3_CH3), Some(Signal::TIM3_CH4)],
+    [Some(Signal::TIM4_CH1), Some(Signal::TIM4_CH2), Some(Signal::TIM4_CH3), Some(Signal::TIM4_CH4)],
+    [Some(Signal::TIM5_CH1), Some(Signal::TIM5_CH2), Some(Signal::TIM5_CH3), Some(Signal::TIM5_CH4)],
+    [None, None, None, None],
+    [None, None, None, None],
+    [Some(Signal::TIM8_CH1), Some(Signal::TIM8_CH2), Some(Signal::TIM8_CH3), Some(Signal::TIM8_CH4)],
+    [Some(Signal::TIM9_CH1), Some(Signal::TIM9_CH2), None, None],
+    [Some(Signal::TIM10_CH1), None, None, None],
+    [Some(Signal::TIM11_CH1), None, None, None],
+    [Some(Signal::TIM12_CH1), Some(Signal::TIM12_CH2), None, None],
+    [Some(Signal::TIM13_CH1), None, None, None],
+    [Some(Signal::TIM14_CH1), None, None, None]
+];
+
+/// Computes the prescaler and auto-reload values giving an update event every
+/// `ticks` timer clock cycles. The prescaler is kept as low as possible for
+/// the best resolution.
+pub fn compute_period(ticks: u64, max_reload: u32) -> Result<(u16, u32), &'static str> {
+    if ticks == 0 {
+        return Err("The timer period is too short.");
+    }
+    let prescaler = (ticks - 1) / ((max_reload as u64) + 1);
+    if 0xFFFF < prescaler {
+        return Err("The timer period is too long.");
+    }
+    let divider = prescaler + 1;
+    let reload = (ticks + divider / 2) / divider;
+    if reload < 1 {
+        return Err("The timer period is too short.");
+    }
+    Ok((prescaler as u16, (reload - 1) as u32))
+}
+
+pub struct TimerPeripheral {
+    pub base_address: *mut TimerRegisters,
+    pub clock: rcc::RCCPeripheral,
+    pub timer_type: TimerType
+}
+unsafe impl Sync for TimerPeripheral {}
+
+impl TimerPeripheral {
+    /// Timer number, 1 for TIM1 up to 14 for TIM14.
+    pub fn number(&self) -> usize {
+        match self.clock.clock {
+            rcc::Clock::TIM1 => 1,
+            rcc::Clock::TIM2 => 2,
+            rcc::Clock::TIM3 => 3,
+            rcc::Clock::TIM4 => 4,
+            rcc::Clock::TIM5 => 5,
+            rcc::Clock::TIM6 => 6,
+            rcc::Clock::TIM7 => 7,
+            rcc::Clock::TIM8 => 8,
+            rcc::Clock::TIM9 => 9,
+            rcc::Clock::TIM10 => 10,
+            rcc::Clock::TIM11 => 11,
+            rcc::Clock::TIM12 => 12,
+            rcc::Clock::TIM13 => 13,
+            rcc::Clock::TIM14 => 14,
+            _ => 0
+        }
+    }
+
+    /// The type the timer actually is, based on its number.
+    fn hardware_type(&self) -> Option<TimerType> {
+        match self.number() {
+            1 | 8 => Some(TimerType::Advanced),
+            6 | 7 => Some(TimerType::Basic),
+            0 => None,
+            _ => Some(TimerType::GeneralPurpose)
+        }
+    }
+
+    /// Number of capture/compare channels.
+    pub fn channel_count(&self) -> usize {
+        match self.number() {
+            1...5 | 8 => 4,
+            9 | 12 => 2,
+            10 | 11 | 13 | 14 => 1,
+            _ => 0
+        }
+    }
+
+    /// TIM2 and TIM5 have 32-bit counters, the others 16-bit ones.
+    pub fn is_32bit(&self) -> bool {
+        match self.number() {
+            2 | 5 => true,
+            _ => false
+        }
+    }
+
+    pub fn max_reload(&self) -> u32 {
+        if self.is_32bit() { 0xFFFFFFFF } else { 0xFFFF }
+    }
+
+    /// Interrupt lines this timer's events are routed to.
+    pub fn irqs(&self) -> &'static [IRQType] {
+        match self.number() {
+            1...14 => IRQS[self.number() - 1],
+            _ => &NO_IRQS
+        }
+    }
+
+    fn registers(&self) -> &mut TimerRegisters {
+        unsafe { &mut *self.base_address }
+    }
+
+    /// Fails if `channel` does not exist on this timer.
+    pub fn require_channel(&self, channel: Channel) -> Result<(), String> {
+        if self.channel_count() <= (channel as usize) {
+            return Err("This channel does not exist on this timer.".to_string());
+        }
+        Ok(())
+    }
+
+    /// Updates the CCMR1/CCMR2 fields of `channel`.
+    fn update_ccmr(&self, channel: Channel, value: u32, mask: u32) {
+        let shift = channel.ccmr_shift();
+        let tim = self.registers();
+        if (channel as u32) < 2 {
+            tim.capture_compare_mode1.update(value << shift, mask << shift);
+        } else {
+            tim.capture_compare_mode2.update(value << shift, mask << shift);
+        }
+    }
+
+    /// Updates the CCER fields of `channel`.
+    fn update_ccer(&self, channel: Channel, value: u32, mask: u32) {
+        let shift = channel.ccer_shift();
+        self.registers().capture_compare_enable.update(value << shift, mask << shift);
+    }
+
+    pub fn capture_compare(&self, channel: Channel) -> u32 {
+        self.registers().capture_compare[channel as usize].read()
+    }
+
+    pub fn set_capture_compare(&self, channel: Channel, value: u32) {
+        self.registers().capture_compare[channel as usize].write(value);
+    }
+
+    pub fn auto_reload(&self) -> u32 {
+        self.registers().auto_reload.read()
+    }
+
+    /// Signal carrying `channel` of this timer on a pin.
+    pub fn channel_signal(&self, channel: Channel) -> Option<Signal> {
+        match self.handler_id() {
+            Some(id) => CHANNEL_SIGNALS[id][channel as usize],
+            None => None
+        }
+    }
+
+    /// Signal carrying the complementary output of `channel` (TIM1 and TIM8
+    /// channels 1 to 3).
+    pub fn complementary_signal(&self, channel: Channel) -> Option<Signal> {
+        match (self.number(), channel) {
+            (1, Channel::Channel1) => Some(Signal::TIM1_CH1N),
+            (1, Channel::Channel2) => Some(Signal::TIM1_CH2N),
+            (1, Channel::Channel3) => Some(Signal::TIM1_CH3N),
+            (8, Channel::Channel1) => Some(Signal::TIM8_CH1N),
+            (8, Channel::Channel2) => Some(Signal::TIM8_CH2N),
+            (8, Channel::Channel3) => Some(Signal::TIM8_CH3N),
+            _ => None
+        }
+    }
+
+    /// Signal carrying the break input (TIM1 and TIM8).
+    pub fn break_signal(&self) -> Option<Signal> {
+        match self.number() {
+            1 => Some(Signal::TIM1_BKIN),
+            8 => Some(Signal::TIM8_BKIN),
+            _ => None
+        }
+    }
+
+    /// Checks `pin` is set to the alternate function carrying `signal`.
+    pub fn check_pin(&self, pin: &PinPeripheral, signal: Option<Signal>) -> Result<(), String> {
+        match signal {
+            Some(signal) => af::check_pin(pin, signal),
+            None => Err("This timer has no such signal.".to_string())
+        }
+    }
+
+    /// Frequency of the timer's input clock.
+    pub fn input_clock(&self) -> usize {
+        self.clock.get_clock()
+    }
+
+    /// Fails unless this timer is at least of type `required`.
+    pub fn require(&self, required: TimerType) -> Result<(), String> {
+        let supported = match (required, self.timer_type) {
+            (TimerType::Basic, _) => true,
+            (TimerType::GeneralPurpose, TimerType::Basic) => false,
+            (TimerType::GeneralPurpose, _) => true,
+            (TimerType::Advanced, t) => t == TimerType::Advanced
+        };
+        if !supported {
+            return Err("This feature is not available on this timer.".to_string());
+        }
+        Ok(())
+    }
+
+    /// Index of this timer in the handlers table, `None` when the clock is
+    /// not a timer's.
+    pub fn handler_id(&self) -> Option<usize> {
+        match self.number() {
+            0 => None,
+            n => Some(n - 1)
+        }
+    }
+
+    /// Routes this timer's interrupts to `callback` and enables them in the
+    /// NVIC. Which events raise an interrupt is set in DIER.
+    pub fn attach(&self, callback: TimerCallback, argument: usize) -> Result<(), String> {
+        let id = match self.handler_id() {
+            Some(id) => id,
+            None => return Err("Invalid timer clock.".to_string())
+        };
+        for irq in self.irqs() {
+            irq.disable();
+        }
+        unsafe {
+            HANDLERS[id] = TimerHandler {
+                registers: self.base_address,
+                callback: Some(callback),
+                argument: argument
+            };
+        }
+        for irq in self.irqs() {
+            irq.enable();
+        }
+        Ok(())
+    }
+
+    /// Forgets this timer's callback. The interrupt lines are left enabled
+    /// as they may be shared with another timer.
+    pub fn detach(&self) {
+        let id = match self.handler_id() {
+            Some(id) => id,
+            None => return
+        };
+        self.registers().dma_interrupt_enable.update(0, DIER_INTERRUPTS);
+        ::critical_section(|| unsafe {
+            HANDLERS[id] = TimerHandler::new();
+        });
+    }
+
+    /// Programs PSC and ARR for an update event every `ticks` input clock
+    /// cycles. The new values are loaded immediately.
+    pub fn set_period_ticks(&self, ticks: u64) -> Result<(), String> {
+        let (prescaler, reload) = match compute_period(ticks, self.max_reload()) {
+            Ok(res) => res,
+            Err(msg) => return Err(msg.to_string())
+        };
+        let tim = self.registers();
+        tim.prescaler.write(prescaler as u32);
+        tim.auto_reload.write(reload);
+        // generate an update to load the prescaler without raising UIF
+        tim.control1.update(CR1_URS, CR1_URS);
+        tim.event_generation.write(EGR_UG);
+        Ok(())
+    }
+
+    /// Programs PSC and ARR for `frequency` update events per second.
+    pub fn set_frequency(&self, frequency: u32) -> Result<(), String> {
+        if frequency == 0 {
+            return Err("The timer frequency must not be null.".to_string());
+        }
+        let clk = self.input_clock() as u64;
+        self.set_period_ticks((clk + (frequency as u64) / 2) / (frequency as u64))
+    }
+
+    /// Programs PSC and ARR for an update event every `period_us` microseconds.
+    pub fn set_period_us(&self, period_us: u32) -> Result<(), String> {
+        let clk = self.input_clock() as u64;
+        self.set_period_ticks((clk * (period_us as u64) + 500_000) / 1_000_000)
+    }
+
+    /// Timer ticks between update events, prescaler included.
+    pub fn period_ticks(&self) -> u64 {
+        let tim = self.registers();
+        ((tim.prescaler.read() as u64) + 1) * ((tim.auto_reload.read() as u64) + 1)
+    }
+
+    /// Number of update events to skip before raising one (advanced timers
+    /// only).
+    pub fn set_repetition(&self, count: u8) -> Result<(), String> {
+        if let Err(msg) = self.require(TimerType::Advanced) {
+            return Err(msg);
+        }
+        self.registers().repetition_counter.write(count as u32);
+        Ok(())
+    }
+
+    pub fn counter(&self) -> u32 {
+        self.registers().counter.read()
+    }
+
+    pub fn set_counter(&self, value: u32) {
+        self.registers().counter.write(value);
+    }
+
+    pub fn is_running(&self) -> bool {
+        (self.registers().control1.read() & CR1_CEN) == CR1_CEN
+    }
+
+    pub fn start(&self) {
+        self.registers().control1.update(CR1_CEN, CR1_CEN);
+    }
+
+    pub fn stop(&self) {
+        self.registers().control1.update(0, CR1_CEN);
+    }
+
+    /// Calls `callback` `frequency` times per second from the update
+    /// interrupt.
+    pub fn start_periodic(&self, frequency: u32, callback: TimerCallback, argument: usize) -> Result<(), String> {
+        self.stop();
+        if let Err(msg) = self.set_frequency(frequency) {
+            return Err(msg);
+        }
+
+        let tim = self.registers();
+        tim.control1.update(CR1_ARPE, CR1_ARPE | CR1_OPM);
+        tim.status.write(0);
+        if let Err(msg) = self.attach(callback, argument) {
+            return Err(msg);
+        }
+        tim.dma_interrupt_enable.update(DIER_UIE, DIER_UIE);
+        self.start();
+        Ok(())
+    }
+
+    /// Calls `callback` once, `delay_us` microseconds from now. The counter
+    /// stops by itself at the update event.
+    pub fn start_one_pulse(&self, delay_us: u32, callback: TimerCallback, argument: usize) -> Result<(), String> {
+        self.stop();
+        if let Err(msg) = self.set_period_us(delay_us) {
+            return Err(msg);
+        }
+
+        let tim = self.registers();
+        tim.control1.update(CR1_OPM, CR1_OPM);
+        tim.counter.write(0);
+        tim.status.write(0);
+        if let Err(msg) = self.attach(callback, argument) {
+            return Err(msg);
+        }
+        tim.dma_interrupt_enable.update(DIER_UIE, DIER_UIE);
+        self.start();
+        Ok(())
+    }
+}
+
+impl Peripheral for TimerPeripheral {
+    fn init(&self) -> Result<(), String> {
+        match self.hardware_type() {
+            Some(t) if t == self.timer_type => {},
+            Some(_) => return Err("The timer type does not match this timer.".to_string()),
+            None => return Err("Invalid timer clock.".to_string())
+        }
+
+        init_peripheral![Some(&self.clock)];
+
+        let tim = self.registers();
+        tim.control1.write(0);
+        tim.dma_interrupt_enable.write(0);
+        tim.status.write(0);
+
+        Ok(())
+    }
+
+    fn deinit(&self) -> Result<(), String> {
+        self.stop();
+        self.detach();
+        self.clock.deinit()
+    }
+}
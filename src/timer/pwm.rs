@@ -0,0 +1,280 @@
+use collections::string::String;
+use collections::string::ToString;
+
+use Peripheral;
+use gpio::PinPeripheral;
+use super::{Channel, TimerPeripheral, TimerType};
+use super::flags::*;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum PwmMode {
+    /// Active while the counter is below the compare value.
+    Mode1 = 6,
+    /// Inactive while the counter is below the compare value.
+    Mode2 = 7
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Alignment {
+    Edge = 0,
+    /// Compare flags are set while counting down.
+    CenterDown = 1,
+    /// Compare flags are set while counting up.
+    CenterUp = 2,
+    /// Compare flags are set in both directions.
+    CenterBoth = 3
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow
+}
+
+/// Prescaler and auto-reload values for a center-aligned period of
+/// `half_ticks` up then as many down. The counter goes from 0 to ARR and back
+/// so the period is 2 * ARR counter clocks.
+pub fn compute_center_period(half_ticks: u64, max_reload: u32) -> Result<(u16, u32), &'static str> {
+    if half_ticks == 0 {
+        return Err("The timer period is too short.");
+    }
+    let prescaler = (half_ticks - 1) / (max_reload as u64);
+    if 0xFFFF < prescaler {
+        return Err("The timer period is too long.");
+    }
+    let divider = prescaler + 1;
+    let reload = (half_ticks + divider / 2) / divider;
+    if reload < 1 {
+        return Err("The timer period is too short.");
+    }
+    Ok((prescaler as u16, reload as u32))
+}
+
+/// Returns the DTG field value giving at least `ticks` dead-time clock
+/// periods (RM0033, TIMx_BDTR).
+pub fn compute_dead_time(ticks: u32) -> Result<u8, &'static str> {
+    if ticks < 128 {
+        Ok(ticks as u8)
+    } else if ticks <= 254 {
+        // (64 + DTG[5:0]) * 2
+        Ok(0x80 | (((ticks + 1) / 2 - 64) as u8))
+    } else if ticks <= 504 {
+        // (32 + DTG[4:0]) * 8
+        Ok(0xC0 | (((ticks + 7) / 8 - 32) as u8))
+    } else if ticks <= 1008 {
+        // (32 + DTG[4:0]) * 16
+        Ok(0xE0 | (((ticks + 15) / 16 - 32) as u8))
+    } else {
+        Err("The dead time is too long.")
+    }
+}
+
+impl TimerPeripheral {
+    /// Sets the PWM frequency and counter alignment shared by all the
+    /// channels of this timer. In center-aligned modes the counter goes up
+    /// then down so each period is twice as many ticks.
+    pub fn configure_pwm(&self, frequency: u32, alignment: Alignment) -> Result<(), String> {
+        if let Err(msg) = self.require(TimerType::GeneralPurpose) {
+            return Err(msg);
+        }
+        if (alignment != Alignment::Edge) && (self.channel_count() < 4) {
+            return Err("This timer does not support center-aligned mode.".to_string());
+        }
+        if frequency == 0 {
+            return Err("The PWM frequency must not be null.".to_string());
+        }
+
+        let clk = self.input_clock() as u64;
+        let frequency = frequency as u64;
+
+        self.stop();
+        let tim = self.registers();
+        tim.control1.update(
+            CR1_ARPE | ((alignment as u32) << CR1_CMS_SHIFT),
+            CR1_ARPE | CR1_CMS_MASK | CR1_DIR | CR1_OPM
+        );
+        if alignment == Alignment::Edge {
+            return self.set_period_ticks((clk + frequency / 2) / frequency);
+        }
+
+        let half_ticks = (clk + frequency) / (2 * frequency);
+        let (prescaler, reload) = match compute_center_period(half_ticks, self.max_reload()) {
+            Ok(res) => res,
+            Err(msg) => return Err(msg.to_string())
+        };
+        tim.prescaler.write(prescaler as u32);
+        tim.auto_reload.write(reload);
+        // generate an update to load the prescaler without raising UIF
+        tim.control1.update(CR1_URS, CR1_URS);
+        tim.event_generation.write(EGR_UG);
+        Ok(())
+    }
+
+    /// Whether the counter runs in one of the center-aligned modes.
+    pub fn is_center_aligned(&self) -> bool {
+        (self.registers().control1.read() & CR1_CMS_MASK) != 0
+    }
+
+    /// Inserts `dead_time_ns` of dead time between complementary outputs
+    /// (advanced timers only).
+    pub fn set_dead_time(&self, dead_time_ns: u32) -> Result<(), String> {
+        if let Err(msg) = self.require(TimerType::Advanced) {
+            return Err(msg);
+        }
+        let clk = self.input_clock() as u64;
+        let ticks = ((dead_time_ns as u64) * clk + 999_999_999) / 1_000_000_000;
+        let dtg = match compute_dead_time(ticks as u32) {
+            Ok(dtg) => dtg,
+            Err(msg) => return Err(msg.to_string())
+        };
+        self.registers().break_dead_time.update(dtg as u32, BDTR_DTG_MASK);
+        Ok(())
+    }
+
+    /// Enables the break input: outputs go to their idle state as soon as it
+    /// becomes active (advanced timers only). With `automatic_restart` they
+    /// are enabled again at the next update event once the break is gone.
+    pub fn enable_break(&self, pin: Option<&PinPeripheral>, polarity: Polarity, automatic_restart: bool) -> Result<(), String> {
+        if let Err(msg) = self.require(TimerType::Advanced) {
+            return Err(msg);
+        }
+        if let Some(pin) = pin {
+            if let Err(msg) = self.check_pin(pin, self.break_signal()) {
+                return Err(msg);
+            }
+            if let Err(msg) = pin.init() {
+                return Err(msg);
+            }
+        }
+
+        let mut bdtr = BDTR_BKE;
+        if polarity == Polarity::ActiveHigh {
+            bdtr |= BDTR_BKP;
+        }
+        if automatic_restart {
+            bdtr |= BDTR_AOE;
+        }
+        self.registers().break_dead_time.update(bdtr, BDTR_BKE | BDTR_BKP | BDTR_AOE);
+        Ok(())
+    }
+
+    pub fn disable_break(&self) {
+        self.registers().break_dead_time.update(0, BDTR_BKE | BDTR_AOE);
+    }
+
+    /// Enables the outputs of an advanced timer (MOE), which the break input
+    /// clears.
+    pub fn enable_outputs(&self) {
+        if self.timer_type == TimerType::Advanced {
+            self.registers().break_dead_time.update(BDTR_MOE, BDTR_MOE);
+        }
+    }
+
+    pub fn disable_outputs(&self) {
+        if self.timer_type == TimerType::Advanced {
+            self.registers().break_dead_time.update(0, BDTR_MOE);
+        }
+    }
+}
+
+/// A PWM output on a timer channel.
+///
+/// The timer frequency and alignment are set with `configure_pwm`. Compare
+/// values are preloaded so a new duty cycle only takes effect at the next
+/// update event.
+pub struct PwmChannel<'a> {
+    pub timer: &'a TimerPeripheral,
+    pub channel: Channel,
+    pub mode: PwmMode,
+    pub polarity: Polarity,
+    pub pin: Option<&'a PinPeripheral<'a>>,
+    /// CHxN output, TIM1/TIM8 channels 1 to 3 only.
+    pub complementary_pin: Option<&'a PinPeripheral<'a>>
+}
+unsafe impl<'a> Sync for PwmChannel<'a> {}
+
+impl<'a> PwmChannel<'a> {
+    /// Duty cycle matching a 100% output, in ticks: ARR + 1 when edge
+    /// aligned, ARR when center aligned.
+    pub fn max_duty(&self) -> u32 {
+        let reload = self.timer.auto_reload() as u64;
+        let max = if self.timer.is_center_aligned() { reload } else { reload + 1 };
+        if max > 0xFFFFFFFF { 0xFFFFFFFF } else { max as u32 }
+    }
+
+    pub fn duty_ticks(&self) -> u32 {
+        self.timer.capture_compare(self.channel)
+    }
+
+    pub fn set_duty_ticks(&self, ticks: u32) {
+        self.timer.set_capture_compare(self.channel, ticks);
+    }
+
+    /// Sets the duty cycle to `numerator / denominator`.
+    pub fn set_duty(&self, numerator: u32, denominator: u32) -> Result<(), String> {
+        if (denominator == 0) || (denominator < numerator) {
+            return Err("The duty cycle must be in [0; 1].".to_string());
+        }
+        let ticks = ((self.max_duty() as u64) * (numerator as u64)) / (denominator as u64);
+        self.set_duty_ticks(ticks as u32);
+        Ok(())
+    }
+
+    pub fn enable(&self) {
+        let mut ccer = CCER_CCE;
+        if self.complementary_pin.is_some() {
+            ccer |= CCER_CCNE;
+        }
+        self.timer.update_ccer(self.channel, ccer, CCER_CCE | CCER_CCNE);
+        self.timer.enable_outputs();
+    }
+
+    pub fn disable(&self) {
+        self.timer.update_ccer(self.channel, 0, CCER_CCE | CCER_CCNE);
+    }
+}
+
+impl<'a> Peripheral for PwmChannel<'a> {
+    fn init(&self) -> Result<(), String> {
+        if let Err(msg) = self.timer.require(TimerType::GeneralPurpose) {
+            return Err(msg);
+        }
+        if let Err(msg) = self.timer.require_channel(self.channel) {
+            return Err(msg);
+        }
+        if let Some(pin) = self.pin {
+            if let Err(msg) = self.timer.check_pin(pin, self.timer.channel_signal(self.channel)) {
+                return Err(msg);
+            }
+        }
+        if let Some(pin) = self.complementary_pin {
+            if let Err(msg) = self.timer.require(TimerType::Advanced) {
+                return Err(msg);
+            }
+            if self.channel == Channel::Channel4 {
+                return Err("Channel 4 has no complementary output.".to_string());
+            }
+            if let Err(msg) = self.timer.check_pin(pin, self.timer.complementary_signal(self.channel)) {
+                return Err(msg);
+            }
+        }
+        init_peripheral![self.pin, self.complementary_pin];
+
+        // output, preloaded compare
+        self.timer.update_ccmr(
+            self.channel,
+            CCMR_OCPE | ((self.mode as u32) << CCMR_OCM_SHIFT),
+            CCMR_CHANNEL_MASK
+        );
+        let polarity = if self.polarity == Polarity::ActiveLow { CCER_CCP | CCER_CCNP } else { 0 };
+        self.timer.update_ccer(self.channel, polarity, CCER_CHANNEL_MASK);
+
+        Ok(())
+    }
+
+    fn deinit(&self) -> Result<(), String> {
+        self.disable();
+        self.timer.update_ccmr(self.channel, 0, CCMR_CHANNEL_MASK);
+        Ok(())
+    }
+}
@@ -0,0 +1,236 @@
+use collections::string::String;
+use collections::string::ToString;
+
+use super::*;
+
+/// What a master timer outputs on TRGO (MMS).
+#[derive(Copy, Clone, PartialEq)]
+pub enum MasterMode {
+    Reset = 0,
+    /// Counter enable, used to start slaves together.
+    Enable = 1,
+    /// Update event, used to clock a slave or trigger conversions.
+    Update = 2,
+    ComparePulse = 3,
+    Compare1 = 4,
+    Compare2 = 5,
+    Compare3 = 6,
+    Compare4 = 7
+}
+
+/// How a slave timer reacts to its trigger input (SMS).
+#[derive(Copy, Clone, PartialEq)]
+pub enum SlaveMode {
+    Disabled = 0,
+    /// The counter restarts on each trigger.
+    Reset = 4,
+    /// The counter only runs while the trigger is high.
+    Gated = 5,
+    /// The counter starts on the trigger.
+    Trigger = 6,
+    /// The counter counts triggers.
+    ExternalClock = 7
+}
+
+// Masters connected to ITR0 to ITR3 of each timer, all `None` when the timer
+// cannot be a slave (RM0033, TIMx internal trigger connection tables).
+static ITR_TABLE: [[Option<usize>; 4]; 14] = [
+    [Some(5), Some(2), Some(3), Some(4)],   // TIM1
+    [Some(1), Some(8), Some(3), Some(4)],   // TIM2
+    [Some(1), Some(2), Some(5), Some(4)],   // TIM3
+    [Some(1), Some(2), Some(3), Some(8)],   // TIM4
+    [Some(2), Some(3), Some(4), Some(8)],   // TIM5
+    [None, None, None, None],               // TIM6
+    [None, None, None, None],               // TIM7
+    [Some(1), Some(2), Some(4), Some(5)],   // TIM8
+    [Some(2), Some(3), Some(10), Some(11)], // TIM9
+    [None, None, None, None],               // TIM10
+    [None, None, None, None],               // TIM11
+    [Some(4), Some(5), Some(13), Some(14)], // TIM12
+    [None, None, None, None],               // TIM13
+    [None, None, None, None]                // TIM14
+];
+
+/// Internal trigger (the TS value) through which timer `master` reaches
+/// timer `slave`, if they are connected.
+pub fn internal_trigger(slave: usize, master: usize) -> Option<u32> {
+    if (slave < 1) || (slave > 14) {
+        return None;
+    }
+    for itr in 0..4 {
+        if ITR_TABLE[slave - 1][itr] == Some(master) {
+            return Some(itr as u32);
+        }
+    }
+    None
+}
+
+/// Regular ADC external trigger (EXTSEL) for the TRGO of `timer`.
+pub fn adc_trigger(timer: usize) -> Option<u32> {
+    match timer {
+        2 => Some(6),
+        3 => Some(8),
+        8 => Some(14),
+        _ => None
+    }
+}
+
+/// DAC trigger (TSELx) for the TRGO of `timer`.
+pub fn dac_trigger(timer: usize) -> Option<u32> {
+    match timer {
+        6 => Some(0),
+        8 => Some(1),
+        7 => Some(2),
+        5 => Some(3),
+        2 => Some(4),
+        4 => Some(5),
+        _ => None
+    }
+}
+
+impl TimerPeripheral {
+    /// Selects what this timer outputs on TRGO. Only TIM1 to TIM8 have one.
+    pub fn set_master_mode(&self, mode: MasterMode) -> Result<(), String> {
+        if let Err(msg) = self.check_master_mode(mode) {
+            return Err(msg);
+        }
+        self.registers().control2.update((mode as u32) << CR2_MMS_SHIFT, CR2_MMS_MASK);
+        Ok(())
+    }
+
+    /// Fails unless `mode` can be output on TRGO by this timer.
+    fn check_master_mode(&self, mode: MasterMode) -> Result<(), String> {
+        match self.number() {
+            1...8 => {},
+            _ => return Err("This timer has no trigger output.".to_string())
+        }
+        if (self.timer_type == TimerType::Basic) && ((mode as u32) > 2) {
+            return Err("Basic timers only output reset, enable or update.".to_string());
+        }
+        Ok(())
+    }
+
+    /// TS value through which `master` triggers this timer.
+    fn trigger_from(&self, master: &TimerPeripheral) -> Result<u32, String> {
+        match internal_trigger(self.number(), master.number()) {
+            Some(ts) => Ok(ts),
+            None => Err("These timers are not connected by an internal trigger.".to_string())
+        }
+    }
+
+    /// Makes this timer a slave of `master` in `mode`. Fails if no internal
+    /// trigger connects them.
+    pub fn set_slave(&self, master: &TimerPeripheral, mode: SlaveMode) -> Result<(), String> {
+        let ts = match self.trigger_from(master) {
+            Ok(ts) => ts,
+            Err(msg) => return Err(msg)
+        };
+        self.registers().slave_mode_control.update(
+            (ts << SMCR_TS_SHIFT) | (mode as u32),
+            SMCR_TS_MASK | SMCR_SMS_MASK
+        );
+        Ok(())
+    }
+
+    /// Stops listening to any master.
+    pub fn clear_slave(&self) {
+        self.registers().slave_mode_control.update(0, SMCR_SMS_MASK | SMCR_MSM);
+    }
+
+    /// Counts `master`'s update events, using it as a prescaler for this
+    /// timer.
+    pub fn clock_from(&self, master: &TimerPeripheral) -> Result<(), String> {
+        // check both sides before touching anything
+        if let Err(msg) = self.trigger_from(master) {
+            return Err(msg);
+        }
+        if let Err(msg) = master.check_master_mode(MasterMode::Update) {
+            return Err(msg);
+        }
+        if let Err(msg) = master.set_master_mode(MasterMode::Update) {
+            return Err(msg);
+        }
+        self.set_slave(master, SlaveMode::ExternalClock)
+    }
+
+    /// Only counts while `master`'s TRGO is high, `master` being set up with
+    /// `set_master_mode` (e.g. `Compare1` for its OC1REF).
+    pub fn gate_by(&self, master: &TimerPeripheral) -> Result<(), String> {
+        self.set_slave(master, SlaveMode::Gated)
+    }
+
+    /// Starts this timer and `slaves` on the same clock cycle. All the timers
+    /// must be configured and stopped.
+    pub fn start_synchronized(&self, slaves: &[&TimerPeripheral]) -> Result<(), String> {
+        // check every pairing before touching anything
+        for slave in slaves {
+            if let Err(msg) = slave.trigger_from(self) {
+                return Err(msg);
+            }
+        }
+        if let Err(msg) = self.check_master_mode(MasterMode::Enable) {
+            return Err(msg);
+        }
+        if let Err(msg) = self.set_master_mode(MasterMode::Enable) {
+            return Err(msg);
+        }
+        for slave in slaves {
+            if let Err(msg) = slave.set_slave(self, SlaveMode::Trigger) {
+                return Err(msg);
+            }
+        }
+        // delay the master by the slaves' trigger latency
+        self.registers().slave_mode_control.update(SMCR_MSM, SMCR_MSM);
+        self.start();
+        Ok(())
+    }
+
+    /// Outputs the update event on TRGO and returns the regular EXTSEL value
+    /// the ADC must use to convert on it.
+    pub fn drive_adc(&self) -> Result<u32, String> {
+        let extsel = match adc_trigger(self.number()) {
+            Some(extsel) => extsel,
+            None => return Err("This timer's TRGO cannot trigger ADC conversions.".to_string())
+        };
+        if let Err(msg) = self.set_master_mode(MasterMode::Update) {
+            return Err(msg);
+        }
+        Ok(extsel)
+    }
+
+    /// Outputs the update event on TRGO and returns the TSELx value the DAC
+    /// must use to convert on it.
+    pub fn drive_dac(&self) -> Result<u32, String> {
+        let tsel = match dac_trigger(self.number()) {
+            Some(tsel) => tsel,
+            None => return Err("This timer's TRGO cannot trigger DAC conversions.".to_string())
+        };
+        if let Err(msg) = self.set_master_mode(MasterMode::Update) {
+            return Err(msg);
+        }
+        Ok(tsel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connected_timers() {
+        assert_eq!(internal_trigger(1, 5), Some(0));
+        assert_eq!(internal_trigger(3, 4), Some(3));
+        assert_eq!(internal_trigger(12, 14), Some(3));
+    }
+
+    #[test]
+    fn unconnected_timers() {
+        assert_eq!(internal_trigger(1, 1), None);
+        assert_eq!(internal_trigger(2, 6), None);
+        // timers without trigger input
+        assert_eq!(internal_trigger(6, 0), None);
+        assert_eq!(internal_trigger(14, 0), None);
+        assert_eq!(internal_trigger(0, 1), None);
+        assert_eq!(internal_trigger(15, 1), None);
+    }
+}
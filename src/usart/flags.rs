@@ -0,0 +1,43 @@
+pub const SR_PE: u16 = 0x0001;
+pub const SR_FE: u16 = 0x0002;
+pub const SR_NE: u16 = 0x0004;
+pub const SR_ORE: u16 = 0x0008;
+pub const SR_IDLE: u16 = 0x0010;
+pub const SR_RXNE: u16 = 0x0020;
+pub const SR_TC: u16 = 0x0040;
+pub const SR_TXE: u16 = 0x0080;
+pub const SR_LBD: u16 = 0x0100;
+pub const SR_CTS: u16 = 0x0200;
+pub const SR_ERRORS: u16 = 0x000F;
+
+pub const CR1_SBK: u16 = 0x0001;
+pub const CR1_RWU: u16 = 0x0002;
+pub const CR1_RE: u16 = 0x0004;
+pub const CR1_TE: u16 = 0x0008;
+pub const CR1_IDLEIE: u16 = 0x0010;
+pub const CR1_RXNEIE: u16 = 0x0020;
+pub const CR1_TCIE: u16 = 0x0040;
+pub const CR1_TXEIE: u16 = 0x0080;
+pub const CR1_PEIE: u16 = 0x0100;
+pub const CR1_PS: u16 = 0x0200;
+pub const CR1_PCE: u16 = 0x0400;
+pub const CR1_WAKE: u16 = 0x0800;
+pub const CR1_M: u16 = 0x1000;
+pub const CR1_UE: u16 = 0x2000;
+pub const CR1_OVER8: u16 = 0x8000;
+
+pub const CR2_STOP_MASK: u16 = 0x3000;
+pub const CR2_STOP_SHIFT: u16 = 12;
+
+pub const CR3_EIE: u16 = 0x0001;
+pub const CR3_IREN: u16 = 0x0002;
+pub const CR3_IRLP: u16 = 0x0004;
+pub const CR3_HDSEL: u16 = 0x0008;
+pub const CR3_NACK: u16 = 0x0010;
+pub const CR3_SCEN: u16 = 0x0020;
+pub const CR3_DMAR: u16 = 0x0040;
+pub const CR3_DMAT: u16 = 0x0080;
+pub const CR3_RTSE: u16 = 0x0100;
+pub const CR3_CTSE: u16 = 0x0200;
+pub const CR3_CTSIE: u16 = 0x0400;
+pub const CR3_ONEBIT: u16 = 0x0800;
@@ -0,0 +1,806 @@
+use core::intrinsics;
+use collections::string::String;
+use collections::string::ToString;
+
+use silica::peripheral::serial::{BitCount, Parity, StopBit, Serial as ISerial};
+use silica::sync::mpsc::Sender;
+use silica::io::{Read, Write, Receive, Error};
+
+use rcc;
+use IRQType;
+use AdvancedPeripheralBus;
+use Peripheral;
+use critical_section;
+use registers::*;
+use dma::{self, DMAStreamPeripheral, DMAStreamRegisters};
+use gpio::PinPeripheral;
+use gpio::af::{self, Signal};
+
+mod flags;
+
+pub use self::flags::*;
+
+/// Size of the statically allocated receive and transmit buffers.
+pub const BUFFER_SIZE: usize = 256;
+
+/// Single producer, single consumer byte queue shared between a thread and an
+/// ISR. The producer only moves `head`, the consumer only moves `tail`.
+struct RingBuffer {
+    data: [u8; BUFFER_SIZE],
+    head: usize,
+    tail: usize
+}
+impl RingBuffer {
+    const fn new() -> RingBuffer {
+        RingBuffer {
+            data: [0; BUFFER_SIZE],
+            head: 0,
+            tail: 0
+        }
+    }
+    fn push(&mut self, byte: u8) -> bool {
+        let (head, tail) = unsafe {
+            (intrinsics::volatile_load(&self.head), intrinsics::volatile_load(&self.tail))
+        };
+        let next = (head + 1) % BUFFER_SIZE;
+        if next == tail {
+            return false;
+        }
+        self.data[head] = byte;
+        unsafe { intrinsics::volatile_store(&mut self.head, next); }
+        true
+    }
+    fn pop(&mut self) -> Option<u8> {
+        let (head, tail) = unsafe {
+            (intrinsics::volatile_load(&self.head), intrinsics::volatile_load(&self.tail))
+        };
+        if head == tail {
+            return None;
+        }
+        let byte = self.data[tail];
+        unsafe { intrinsics::volatile_store(&mut self.tail, (tail + 1) % BUFFER_SIZE); }
+        Some(byte)
+    }
+    fn clear(&mut self) {
+        unsafe {
+            let head = intrinsics::volatile_load(&self.head);
+            intrinsics::volatile_store(&mut self.tail, head);
+        }
+    }
+}
+
+/// State shared between a `Serial` and its interrupt handlers.
+///
+/// When a receive DMA stream is used, `rx.data` is the circular DMA buffer;
+/// `rx.tail` is the read position and `rx_position` the last known DMA write
+/// position. When a transmit DMA stream is used, `tx.data` holds the bytes
+/// being sent.
+struct Context {
+    registers: *mut USARTRegisters,
+    rx: RingBuffer,
+    tx: RingBuffer,
+    errors: u16,
+    rx_stream: *mut DMAStreamRegisters,
+    rx_position: usize,
+    rx_pending: usize,
+    /// Half and full transfer events not yet matched by `sync_rx_dma`.
+    rx_halves: isize,
+    notify: Option<Sender<()>>
+}
+impl Context {
+    const fn new() -> Context {
+        Context {
+            registers: 0 as *mut USARTRegisters,
+            rx: RingBuffer::new(),
+            tx: RingBuffer::new(),
+            errors: 0,
+            rx_stream: 0 as *mut DMAStreamRegisters,
+            rx_position: 0,
+            rx_pending: 0,
+            rx_halves: 0,
+            notify: None
+        }
+    }
+
+    /// Signals the registered receiver, if any, that data is available.
+    fn notify(&self) {
+        if let Some(ref sender) = self.notify {
+            let _ = sender.send(());
+        }
+    }
+
+    /// Accounts for the bytes the receive DMA stream wrote since last call
+    /// and returns how many there were.
+    fn sync_rx_dma(&mut self) -> usize {
+        let remaining = unsafe { (*self.rx_stream).remaining() } as usize;
+        let position = (BUFFER_SIZE - remaining) % BUFFER_SIZE;
+        let mut received = (position + BUFFER_SIZE - self.rx_position) % BUFFER_SIZE;
+
+        // The position alone cannot tell full laps of the buffer apart, the
+        // half and full transfer events reported by the DMA ISR can. Their
+        // balance may briefly be negative when the ISR has not run yet.
+        let half = BUFFER_SIZE / 2;
+        let crossed = (self.rx_position + received) / half - self.rx_position / half;
+        self.rx_halves -= crossed as isize;
+        if self.rx_halves >= 2 {
+            let laps = (self.rx_halves / 2) as usize;
+            received += laps * BUFFER_SIZE;
+            self.rx_halves -= (laps * 2) as isize;
+        }
+
+        self.rx_position = position;
+        self.rx_pending += received;
+        if self.rx_pending > BUFFER_SIZE {
+            // the oldest bytes have been overwritten
+            self.errors |= SR_ORE;
+            self.rx_pending = BUFFER_SIZE;
+            self.rx.tail = position;
+        }
+        received
+    }
+}
+
+/// Milliseconds the TX DMA may stall on one frame while closing the port.
+const TX_DRAIN_TIMEOUT: u32 = 100;
+
+static mut CONTEXTS: [Context; 6] = [
+    Context::new(), Context::new(), Context::new(),
+    Context::new(), Context::new(), Context::new()
+];
+
+unsafe fn on_interrupt(id: usize) {
+    let ctx = &mut CONTEXTS[id];
+    if ctx.registers.is_null() {
+        return;
+    }
+    let usart = &mut *ctx.registers;
+    let sr = usart.status.read();
+    let cr1 = usart.control1.read();
+
+    if !ctx.rx_stream.is_null() {
+        if (sr & (SR_IDLE | SR_ERRORS)) != 0 {
+            // reading DR after SR clears IDLE and the error flags, the data
+            // itself has already been fetched by the DMA.
+            usart.data.read();
+            ctx.errors |= sr & SR_ERRORS;
+            if ctx.sync_rx_dma() != 0 {
+                ctx.notify();
+            }
+        }
+    } else if (sr & (SR_RXNE | SR_ORE)) != 0 {
+        // reading DR after SR clears RXNE and the error flags
+        let byte = usart.data.read() as u8;
+        ctx.errors |= sr & SR_ERRORS;
+        if ctx.rx.push(byte) {
+            ctx.notify();
+        } else {
+            ctx.errors |= SR_ORE;
+        }
+    }
+
+    if ((cr1 & CR1_TXEIE) == CR1_TXEIE) && ((sr & SR_TXE) == SR_TXE) {
+        match ctx.tx.pop() {
+            Some(byte) => usart.data.write(byte as u16),
+            None => usart.control1.update(CR1_TCIE, CR1_TXEIE | CR1_TCIE)
+        }
+    }
+
+    if ((cr1 & CR1_TCIE) == CR1_TCIE) && ((sr & SR_TC) == SR_TC) {
+        usart.control1.update(0, CR1_TCIE);
+    }
+}
+
+fn on_rx_dma(id: usize, flags: u32) {
+    let ctx = unsafe { &mut CONTEXTS[id] };
+    if ctx.rx_stream.is_null() {
+        return;
+    }
+    if (flags & dma::ISR_TEIF) == dma::ISR_TEIF {
+        ctx.errors |= SR_ORE;
+    }
+    if (flags & dma::ISR_HTIF) == dma::ISR_HTIF {
+        ctx.rx_halves += 1;
+    }
+    if (flags & dma::ISR_TCIF) == dma::ISR_TCIF {
+        ctx.rx_halves += 1;
+    }
+    if ctx.sync_rx_dma() != 0 {
+        ctx.notify();
+    }
+}
+
+pub unsafe extern "C" fn usart1_handler() {
+    on_interrupt(0);
+}
+pub unsafe extern "C" fn usart2_handler() {
+    on_interrupt(1);
+}
+pub unsafe extern "C" fn usart3_handler() {
+    on_interrupt(2);
+}
+pub unsafe extern "C" fn uart4_handler() {
+    on_interrupt(3);
+}
+pub unsafe extern "C" fn uart5_handler() {
+    on_interrupt(4);
+}
+pub unsafe extern "C" fn usart6_handler() {
+    on_interrupt(5);
+}
+
+#[repr(C)]
+pub struct USARTRegisters {
+    status: Ro<u16>,
+    reserved0: u16,
+    data: Rw<u16>,
+    reserved1: u16,
+    baud_rate: Rw<u16>,
+    reserved2: u16,
+    control1: Rw<u16>,
+    reserved3: u16,
+    control2: Rw<u16>,
+    reserved4: u16,
+    control3: Rw<u16>,
+    reserved5: u16,
+    gtpr_psc: Rw<u8>,
+    gtpr_gt: Rw<u8>,
+    reserved6: u16
+}
+
+pub struct USARTPeripheral<'a> {
+    pub base_address: *mut USARTRegisters,
+    pub clock: rcc::RCCPeripheral,
+    pub isr_id: IRQType,
+    pub dma_rx: Option<&'a DMAStreamPeripheral<'a>>,
+    pub dma_tx: Option<&'a DMAStreamPeripheral<'a>>,
+
+    pub pin_tx: Option<&'a PinPeripheral<'a>>,  // output
+    pub pin_rx: Option<&'a PinPeripheral<'a>>,  // input
+    pub pin_dtr: Option<&'a PinPeripheral<'a>>, // output: data terminal ready
+    pub pin_dcd: Option<&'a PinPeripheral<'a>>, // input: data carier detect
+    pub pin_dsr: Option<&'a PinPeripheral<'a>>, // input: data set ready
+    pub pin_ri: Option<&'a PinPeripheral<'a>>,  // input: ring indicator
+    pub pin_rts: Option<&'a PinPeripheral<'a>>, // output: request to send
+    pub pin_cts: Option<&'a PinPeripheral<'a>>, // input: clear to send
+}
+unsafe impl<'a> Sync for USARTPeripheral<'a> {}
+
+impl<'a> USARTPeripheral<'a> {
+    /// Index of the interrupt context used by this USART.
+    fn context_id(&self) -> Option<usize> {
+        match self.isr_id {
+            IRQType::USART1 => Some(0),
+            IRQType::USART2 => Some(1),
+            IRQType::USART3 => Some(2),
+            IRQType::UART4 => Some(3),
+            IRQType::UART5 => Some(4),
+            IRQType::USART6 => Some(5),
+            _ => None
+        }
+    }
+
+    /// TX, RX, RTS and CTS signals of this USART. UARTs have no flow control.
+    fn signals(&self) -> Option<(Signal, Signal, Option<Signal>, Option<Signal>)> {
+        match self.clock.clock {
+            rcc::Clock::USART1 => Some((Signal::USART1_TX, Signal::USART1_RX,
+                                        Some(Signal::USART1_RTS), Some(Signal::USART1_CTS))),
+            rcc::Clock::USART2 => Some((Signal::USART2_TX, Signal::USART2_RX,
+                                        Some(Signal::USART2_RTS), Some(Signal::USART2_CTS))),
+            rcc::Clock::USART3 => Some((Signal::USART3_TX, Signal::USART3_RX,
+                                        Some(Signal::USART3_RTS), Some(Signal::USART3_CTS))),
+            rcc::Clock::UART4 => Some((Signal::UART4_TX, Signal::UART4_RX, None, None)),
+            rcc::Clock::UART5 => Some((Signal::UART5_TX, Signal::UART5_RX, None, None)),
+            rcc::Clock::USART6 => Some((Signal::USART6_TX, Signal::USART6_RX,
+                                        Some(Signal::USART6_RTS), Some(Signal::USART6_CTS))),
+            _ => None
+        }
+    }
+
+    /// Checks the data and hardware flow control pins can carry their signal.
+    fn check_pins(&self) -> Result<(), String> {
+        let (tx, rx, rts, cts) = match self.signals() {
+            Some(signals) => signals,
+            None => return Err("Invalid USART clock".to_string())
+        };
+        let pins = [(self.pin_tx, Some(tx)), (self.pin_rx, Some(rx)),
+                    (self.pin_rts, rts), (self.pin_cts, cts)];
+        for &(pin, signal) in pins.iter() {
+            match (pin, signal) {
+                (Some(pin), Some(signal)) => {
+                    if let Err(msg) = af::check_pin(pin, signal) {
+                        return Err(msg);
+                    }
+                },
+                (Some(_), None) => return Err("This UART has no hardware flow control".to_string()),
+                (None, _) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Peripheral for USARTPeripheral<'a> {
+    fn init(&self) -> Result<(), String> {
+        let mut cr3 = unsafe { (*self.base_address).control3.read() };
+        let mut cr1 = unsafe { (*self.base_address).control1.read() };
+
+        // setup GPIOs
+        if let Err(msg) = self.check_pins() {
+            return Err(msg);
+        }
+        init_peripheral![self.pin_tx, self.pin_rx]; // data lines
+        init_peripheral![self.pin_dtr, self.pin_dcd, self.pin_dsr, self.pin_ri]; // sw flow control
+        init_peripheral![self.pin_rts, self.pin_cts]; // hw flow control
+        if self.pin_cts.is_some() {
+            cr3 |= CR3_CTSE;
+        }
+        if self.pin_rts.is_some() {
+            cr3 |= CR3_RTSE;
+        }
+        if self.pin_tx.is_some() {
+            cr1 |= CR1_TE;
+        }
+        if self.pin_rx.is_some() {
+            cr1 |= CR1_RE;
+        }
+
+        // enable clock (RCC)
+        init_peripheral![Some(&self.clock)];
+
+        unsafe {
+            (*self.base_address).control3.write(cr3);
+            (*self.base_address).control1.write(cr1);
+        }
+
+        Ok(())
+    }
+
+    fn deinit(&self) -> Result<(), String> {
+        Err("Not yet implemented".to_string())
+    }
+}
+
+/// Baud rate register setting computed by `compute_brr`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct BaudRateConfig {
+    /// Value to write in BRR.
+    pub brr: u16,
+    /// Whether CR1.OVER8 must be set.
+    pub over8: bool,
+    /// Baud rate actually achieved with this setting.
+    pub baudrate: usize,
+    /// Deviation from the requested baud rate in parts per million.
+    pub error_ppm: i32
+}
+
+/// Computes the BRR value giving the closest baud rate to `baudrate` from the
+/// peripheral clock `clk`.
+///
+/// baud = clk / (8 * (2 - OVER8) * USARTDIV), with USARTDIV coded on 12 bits
+/// of mantissa and 4 (OVER8 = 0) or 3 (OVER8 = 1) bits of fraction.
+pub fn compute_brr(clk: usize, baudrate: usize, over8: bool) -> Result<BaudRateConfig, &'static str> {
+    if baudrate == 0 {
+        return Err("The baudrate must not be null.");
+    }
+    let fraction_bits = if over8 { 3 } else { 4 };
+
+    // USARTDIV in 1/8th or 1/16th, rounded to nearest.
+    let div = (clk + baudrate / 2) / baudrate;
+    let mantissa = div >> fraction_bits;
+    let fraction = div & ((1 << fraction_bits) - 1);
+
+    if mantissa == 0 {
+        return Err("This baudrate is too high for this serial port.");
+    }
+    if mantissa >= 4096 {
+        return Err("This baudrate is too low for this serial port.");
+    }
+
+    let achieved = (clk + div / 2) / div;
+    let error_ppm = ((achieved as i64 - baudrate as i64) * 1_000_000) / (baudrate as i64);
+
+    Ok(BaudRateConfig {
+        brr: ((mantissa << 4) | fraction) as u16,
+        over8: over8,
+        baudrate: achieved,
+        error_ppm: error_ppm as i32
+    })
+}
+
+/// Returns the baud rate generated by a BRR value.
+pub fn brr_to_baudrate(clk: usize, brr: u16, over8: bool) -> usize {
+    let mantissa = (brr >> 4) as usize;
+    let div = if over8 {
+        (mantissa << 3) | ((brr & 0x7) as usize)
+    } else {
+        (mantissa << 4) | ((brr & 0xF) as usize)
+    };
+    if div == 0 {
+        return 0;
+    }
+    (clk + div / 2) / div
+}
+
+pub struct Serial<'a> {
+    periph: &'a USARTPeripheral<'a>,
+    context: Option<usize>,
+    // sender given to `on_recv` before `open`
+    notify: Option<Sender<()>>
+}
+impl<'a> Serial<'a> {
+    pub fn from(f: &'a USARTPeripheral<'a>) -> Serial<'a> {
+        Serial {
+            periph: f,
+            context: None,
+            notify: None
+        }
+    }
+
+    fn context(&self) -> Option<&'static mut Context> {
+        match self.context {
+            Some(id) => Some(unsafe { &mut CONTEXTS[id] }),
+            None => None
+        }
+    }
+
+    fn data_address(&self) -> usize {
+        unsafe { &(*self.periph.base_address).data as *const Rw<u16> as usize }
+    }
+}
+impl<'a> ISerial for Serial<'a> {
+    fn setup(&mut self, baudrate:usize, word_len: BitCount, parity: Parity, stop_bit: StopBit) -> Result<(), String> {
+        init_peripheral![Some(&self.periph)];
+        let mut cr3 = unsafe { (*self.periph.base_address).control3.read() };
+        let mut cr1 = unsafe { (*self.periph.base_address).control1.read() };
+        let mut cr2 = unsafe { (*self.periph.base_address).control2.read() };
+
+        let clk = self.periph.clock.get_clock();
+
+        // oversampling by 16 is more tolerant to clock deviations, only fall
+        // back to oversampling by 8 when the baudrate requires it.
+        let config = match compute_brr(clk, baudrate, false) {
+            Ok(config) => config,
+            Err(_) => match compute_brr(clk, baudrate, true) {
+                Ok(config) => config,
+                Err(msg) => return Err(msg.to_string())
+            }
+        };
+        if config.over8 {
+            cr1 |= CR1_OVER8;
+        } else {
+            cr1 &= !CR1_OVER8;
+        }
+
+        // M selects the frame length, parity bit included
+        let parity_bits = match parity {
+            Parity::None => 0,
+            _ => 1
+        };
+        let data_bits = match word_len {
+            BitCount::SevenBits => 7,
+            BitCount::EightBits => 8,
+            BitCount::NineBits => 9
+        };
+        cr1 &= !(CR1_M | CR1_PCE | CR1_PS);
+        match data_bits + parity_bits {
+            8 => {},
+            9 => cr1 |= CR1_M,
+            _ => return Err("Unsupported word length and parity combination.".to_string())
+        }
+        match parity {
+            Parity::None => {},
+            Parity::Even => cr1 |= CR1_PCE,
+            Parity::Odd => cr1 |= CR1_PCE | CR1_PS
+        }
+
+        let stop = match stop_bit {
+            StopBit::OneBit => 0,
+            StopBit::HalfBit => 1,
+            StopBit::TwoBits => 2,
+            StopBit::OneHalfBit => 3
+        };
+        cr2 = (cr2 & !CR2_STOP_MASK) | (stop << CR2_STOP_SHIFT);
+
+        // setup DMA rx
+        init_peripheral![self.periph.dma_rx];
+        if self.periph.dma_rx.is_some() {
+            cr3 |= CR3_DMAR;
+        }
+
+        // setup DMA tx
+        init_peripheral![self.periph.dma_tx];
+        if self.periph.dma_tx.is_some() {
+            cr3 |= CR3_DMAT;
+        }
+
+        unsafe {
+            (*self.periph.base_address).baud_rate.write(config.brr);
+            (*self.periph.base_address).control2.write(cr2);
+            (*self.periph.base_address).control3.write(cr3);
+            (*self.periph.base_address).control1.write(cr1);
+        }
+
+        Ok(())
+    }
+    fn baudrate(&self) -> usize {
+        let clk = self.periph.clock.get_clock();
+        let (brr, over8) = unsafe {
+            let usart = &*self.periph.base_address;
+            (usart.baud_rate.read(), (usart.control1.read() & CR1_OVER8) == CR1_OVER8)
+        };
+        brr_to_baudrate(clk, brr, over8)
+    }
+    fn open(&mut self) -> Result<(), String> {
+        if self.context.is_some() {
+            return Ok(());
+        }
+        let id = match self.periph.context_id() {
+            Some(id) => id,
+            None => return Err("This peripheral has no USART interrupt.".to_string())
+        };
+
+        unsafe {
+            let ctx = &mut CONTEXTS[id];
+            if !ctx.registers.is_null() {
+                return Err("This serial port is already open.".to_string());
+            }
+            ctx.rx.clear();
+            ctx.tx.clear();
+            ctx.errors = 0;
+            ctx.registers = self.periph.base_address;
+            ctx.notify = self.notify.take();
+
+            let mut cr1 = CR1_UE | CR1_PEIE;
+            if let Some(stream) = self.periph.dma_rx {
+                ctx.rx.head = 0;
+                ctx.rx.tail = 0;
+                ctx.rx_position = 0;
+                ctx.rx_pending = 0;
+                ctx.rx_halves = 0;
+                ctx.rx_stream = stream.base_address;
+
+                let res = stream.start(&dma::Transfer {
+                    direction: dma::Direction::PeripheralToMemory,
+                    peripheral: self.data_address(),
+                    peripheral_size: dma::DataSize::Byte,
+                    peripheral_increment: false,
+                    memory: ctx.rx.data.as_mut_ptr() as usize,
+                    memory_size: dma::DataSize::Byte,
+                    memory_increment: true,
+                    count: BUFFER_SIZE as u16,
+                    circular: true,
+                    priority: dma::Priority::High,
+                    interrupts: true
+                });
+                if let Err(msg) = res {
+                    ctx.rx_stream = 0 as *mut DMAStreamRegisters;
+                    ctx.registers = 0 as *mut USARTRegisters;
+                    self.notify = ctx.notify.take();
+                    return Err(msg);
+                }
+                stream.attach(on_rx_dma, id);
+                cr1 |= CR1_IDLEIE;
+            } else {
+                cr1 |= CR1_RXNEIE;
+            }
+
+            let usart = &mut *self.periph.base_address;
+            usart.control3.update(CR3_EIE, CR3_EIE);
+            usart.control1.update(
+                cr1,
+                CR1_UE | CR1_RXNEIE | CR1_IDLEIE | CR1_PEIE | CR1_TXEIE | CR1_TCIE
+            );
+        }
+        self.context = Some(id);
+        self.periph.isr_id.enable();
+
+        Ok(())
+    }
+    fn close(&mut self) {
+        if let Some(ctx) = self.context() {
+            self.periph.isr_id.disable();
+            unsafe {
+                let usart = &mut *self.periph.base_address;
+                usart.control3.update(0, CR3_EIE);
+                usart.control1.update(0, CR1_RXNEIE | CR1_IDLEIE | CR1_PEIE | CR1_TXEIE | CR1_TCIE);
+            }
+            if let Some(stream) = self.periph.dma_rx {
+                stream.detach();
+                let _ = stream.stop();
+            }
+            if let Some(stream) = self.periph.dma_tx {
+                if stream.is_enabled() {
+                    let _ = stream.wait(TX_DRAIN_TIMEOUT);
+                }
+            }
+            ctx.rx_stream = 0 as *mut DMAStreamRegisters;
+            ctx.registers = 0 as *mut USARTRegisters;
+            ctx.notify = None;
+        }
+        self.notify = None;
+        self.context = None;
+    }
+}
+impl<'a> Read for Serial<'a> {
+    fn read(&mut self, dest: &mut [u8]) -> Result<usize, Error> {
+        let ctx = match self.context() {
+            Some(ctx) => ctx,
+            None => return Ok(0usize)
+        };
+
+        let errors = critical_section(|| {
+            if !ctx.rx_stream.is_null() {
+                ctx.sync_rx_dma();
+            }
+            let errors = ctx.errors;
+            ctx.errors = 0;
+            errors
+        });
+        if (errors & SR_ORE) == SR_ORE {
+            return Err(Error::Overrun);
+        } else if (errors & SR_FE) == SR_FE {
+            return Err(Error::Framing);
+        } else if (errors & SR_NE) == SR_NE {
+            return Err(Error::Noise);
+        } else if (errors & SR_PE) == SR_PE {
+            return Err(Error::Parity);
+        }
+
+        if !ctx.rx_stream.is_null() {
+            let count = critical_section(|| {
+                let count = if ctx.rx_pending < dest.len() { ctx.rx_pending } else { dest.len() };
+                for i in 0..count {
+                    dest[i] = ctx.rx.data[(ctx.rx.tail + i) % BUFFER_SIZE];
+                }
+                ctx.rx.tail = (ctx.rx.tail + count) % BUFFER_SIZE;
+                ctx.rx_pending -= count;
+                count
+            });
+            return Ok(count);
+        }
+
+        let mut count = 0;
+        while count < dest.len() {
+            match ctx.rx.pop() {
+                Some(byte) => dest[count] = byte,
+                None => break
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+impl<'a> Write for Serial<'a> {
+    fn write(&mut self, src: &[u8]) -> Result<usize, Error> {
+        let ctx = match self.context() {
+            Some(ctx) => ctx,
+            None => return Ok(0usize)
+        };
+
+        if let Some(stream) = self.periph.dma_tx {
+            if stream.is_enabled() {
+                // previous transfer still in progress
+                return Ok(0usize);
+            }
+            let count = if src.len() < BUFFER_SIZE { src.len() } else { BUFFER_SIZE };
+            if count == 0 {
+                return Ok(0usize);
+            }
+            ctx.tx.data[..count].copy_from_slice(&src[..count]);
+            // the buffer is static and not touched until the stream is idle
+            let res = unsafe { stream.memory_to_peripheral(&ctx.tx.data[..count], self.data_address()) };
+            if res.is_err() {
+                return Err(Error::Other);
+            }
+            return Ok(count);
+        }
+
+        let mut count = 0;
+        for byte in src {
+            if !ctx.tx.push(*byte) {
+                break;
+            }
+            count += 1;
+        }
+
+        if count != 0 {
+            let base_address = self.periph.base_address;
+            critical_section(|| unsafe {
+                (*base_address).control1.update(CR1_TXEIE, CR1_TXEIE);
+            });
+        }
+        Ok(count)
+    }
+}
+impl<'a> Drop for Serial<'a> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+impl<'a> Receive for Serial<'a> {
+    /// Registers `s` to be signaled from the ISR each time bytes are received.
+    /// A previously registered sender is replaced. On a closed handle `s` is
+    /// kept until `open` registers it; `close` drops it.
+    fn on_recv(&mut self, s: Sender<()>) {
+        match self.context {
+            Some(id) => critical_section(|| unsafe {
+                CONTEXTS[id].notify = Some(s);
+            }),
+            None => self.notify = Some(s)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn brr_oversampling_by_16() {
+        // USARTDIV = 16MHz / (16 * 115200) = 8.68, 0.68 * 16 rounds to 11
+        let config = compute_brr(16_000_000, 115_200, false).unwrap();
+        assert_eq!(config.brr, 0x8B);
+        assert!(!config.over8);
+        assert_eq!(config.baudrate, 115_108);
+        assert_eq!(config.error_ppm, -798);
+    }
+
+    #[test]
+    fn brr_oversampling_by_8() {
+        // USARTDIV = 16MHz / (8 * 115200) = 17.36, 0.36 * 8 rounds to 3
+        let config = compute_brr(16_000_000, 115_200, true).unwrap();
+        assert_eq!(config.brr, 0x113);
+        assert!(config.over8);
+        assert_eq!(config.baudrate, 115_108);
+    }
+
+    #[test]
+    fn brr_fraction_carries_into_mantissa() {
+        // USARTDIV = 15.998, the fraction rounds to 16/16
+        let config = compute_brr(16_000_000, 62_507, false).unwrap();
+        assert_eq!(config.brr, 0x100);
+        // USARTDIV = 31.996, the fraction rounds to 8/8 and BRR[3] stays clear
+        let config = compute_brr(16_000_000, 62_507, true).unwrap();
+        assert_eq!(config.brr, 0x200);
+    }
+
+    #[test]
+    fn brr_over8_keeps_bit3_clear() {
+        for baudrate in 1_200..20_000 {
+            let config = compute_brr(30_000_000, baudrate * 50, true).unwrap();
+            assert_eq!(config.brr & 0x8, 0);
+        }
+    }
+
+    #[test]
+    fn brr_out_of_range() {
+        assert!(compute_brr(16_000_000, 0, false).is_err());
+        // USARTDIV = 0.5 without oversampling by 8, 1 with it
+        assert!(compute_brr(16_000_000, 2_000_000, false).is_err());
+        assert_eq!(compute_brr(16_000_000, 2_000_000, true).unwrap().brr, 0x10);
+        // the mantissa needs 13 bits
+        assert!(compute_brr(60_000_000, 900, false).is_err());
+        assert!(compute_brr(60_000_000, 900, true).is_err());
+    }
+
+    #[test]
+    fn brr_round_trip() {
+        let clocks = [8_000_000, 16_000_000, 30_000_000, 42_000_000, 60_000_000];
+        let baudrates = [9_600, 19_200, 38_400, 57_600, 115_200, 230_400, 460_800, 921_600];
+        for &clk in clocks.iter() {
+            for &baudrate in baudrates.iter() {
+                for &over8 in [false, true].iter() {
+                    if let Ok(config) = compute_brr(clk, baudrate, over8) {
+                        assert_eq!(brr_to_baudrate(clk, config.brr, over8), config.baudrate);
+                        assert!(config.error_ppm.abs() < 40_000);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn brr_zero_reads_back_as_zero() {
+        assert_eq!(brr_to_baudrate(16_000_000, 0, false), 0);
+    }
+}